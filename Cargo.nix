@@ -1347,14 +1347,19 @@ in
     src = fetchCrateLocal workspaceSrc;
     dependencies = {
       anyhow = rustPackages."registry+https://github.com/rust-lang/crates.io-index".anyhow."1.0.58" { inherit profileName; };
+      bitflags = rustPackages."registry+https://github.com/rust-lang/crates.io-index".bitflags."1.3.2" { inherit profileName; };
       calloop = rustPackages."registry+https://github.com/rust-lang/crates.io-index".calloop."0.9.3" { inherit profileName; };
       palette = rustPackages."registry+https://github.com/rust-lang/crates.io-index".palette."0.6.1" { inherit profileName; };
       smithay_client_toolkit = rustPackages."registry+https://github.com/rust-lang/crates.io-index".smithay-client-toolkit."0.15.4" { inherit profileName; };
       upower_dbus = rustPackages."registry+https://github.com/rust-lang/crates.io-index".upower_dbus."0.2.0" { inherit profileName; };
       wayland_client = rustPackages."registry+https://github.com/rust-lang/crates.io-index".wayland-client."0.29.4" { inherit profileName; };
+      wayland_commons = rustPackages."registry+https://github.com/rust-lang/crates.io-index".wayland-commons."0.29.4" { inherit profileName; };
       wayland_protocols = rustPackages."registry+https://github.com/rust-lang/crates.io-index".wayland-protocols."0.29.4" { inherit profileName; };
       zbus = rustPackages."registry+https://github.com/rust-lang/crates.io-index".zbus."2.3.2" { inherit profileName; };
     };
+    buildDependencies = {
+      wayland_scanner = buildRustPackages."registry+https://github.com/rust-lang/crates.io-index".wayland-scanner."0.29.4" { profileName = "__noProfile"; };
+    };
   });
   
   "registry+https://github.com/rust-lang/crates.io-index".wayland-client."0.29.4" = overridableMkRustCrate (profileName: rec {
@@ -0,0 +1,15 @@
+use std::path::Path;
+
+use wayland_scanner::Side;
+
+/// Protocols, or versions of them, that wayland-protocols doesn't provide yet
+const PROTOCOLS: &[&str] = &["ext-idle-notify-v1", "wlr-layer-shell-unstable-v1"];
+
+fn main() {
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    for protocol in PROTOCOLS {
+        let source = format!("protocols/{}.xml", protocol);
+        println!("cargo:rerun-if-changed={}", source);
+        wayland_scanner::generate_code(&source, Path::new(&out_dir).join(format!("{}_client_api.rs", protocol)), Side::Client);
+    }
+}
@@ -0,0 +1,130 @@
+//! Battery status for the BSDs, read from FreeBSD's ACPI battery sysctls or from `apm` elsewhere
+use std::process::Command;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::upower::PowerReporter;
+use crate::{ChargeState, PowerState};
+
+/// Neither source can tell us when something changes, so they're polled this often
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Bits of `hw.acpi.battery.state`
+const ACPI_BATT_STAT_DISCHARG: i32 = 0x1;
+const ACPI_BATT_STAT_CHARGING: i32 = 0x2;
+/// All of the bits together mean that no battery is installed
+const ACPI_BATT_STAT_NOT_PRESENT: i32 = 0x7;
+
+/// `apm -b` values
+const APM_BATTERY_CHARGING: i32 = 3;
+const APM_BATTERY_ABSENT: i32 = 4;
+/// `apm -a` value while on mains power
+const APM_AC_ONLINE: i32 = 1;
+
+/// Report the battery status every [`POLL_INTERVAL`]. Fails straight away if neither source
+/// works; problems after that are only reported as failures until it can be read again.
+pub fn spawn_acpi(reporter: PowerReporter) -> anyhow::Result<()> {
+    reporter.report(Some(probe()?));
+    std::thread::spawn(move || {
+        let mut failing = false;
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            match probe() {
+                Ok(state) => {
+                    failing = false;
+                    if !reporter.report(Some(state)) {
+                        // The bar has gone away
+                        return;
+                    }
+                }
+                Err(err) => {
+                    if !failing {
+                        eprintln!("Unable to read the battery status: {:#}", err);
+                    }
+                    failing = true;
+                    reporter.fail();
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+pub fn probe() -> anyhow::Result<PowerState> {
+    sysctl_state().or_else(|sysctl_err| {
+        apm_state().map_err(|apm_err| {
+            anyhow!("No battery information from sysctl ({:#}) or apm ({:#})", sysctl_err, apm_err)
+        })
+    })
+}
+
+/// Run `program` and return what it printed
+fn run(program: &str, args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("Unable to run {}", program))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("{} failed: {}", program, stderr.lines().next().unwrap_or_default().trim());
+    }
+    String::from_utf8(output.stdout).with_context(|| format!("{} printed something other than text", program))
+}
+
+fn parse_number(program: &str, value: Option<&str>) -> anyhow::Result<i32> {
+    let value = value.ok_or_else(|| anyhow!("{} printed too little", program))?.trim();
+    value.parse().with_context(|| format!("{} printed {:?} rather than a number", program, value))
+}
+
+/// The battery as FreeBSD's ACPI battery driver sees it
+fn sysctl_state() -> anyhow::Result<PowerState> {
+    let output = run("sysctl", &["-n", "hw.acpi.battery.life", "hw.acpi.battery.state", "hw.acpi.battery.time"])?;
+    let mut lines = output.lines();
+    let life = parse_number("sysctl", lines.next())?;
+    let battery_state = parse_number("sysctl", lines.next())?;
+    // In minutes, or -1 while on mains power or still estimating
+    let time = parse_number("sysctl", lines.next())?;
+
+    let state = match battery_state {
+        ACPI_BATT_STAT_NOT_PRESENT => ChargeState::Absent,
+        _ if battery_state & ACPI_BATT_STAT_CHARGING != 0 => ChargeState::Charging,
+        _ if battery_state & ACPI_BATT_STAT_DISCHARG != 0 => ChargeState::Discharging,
+        // Neither charging nor discharging, so it's sitting on mains power with nothing left to do
+        _ => ChargeState::FullyCharged,
+    };
+    Ok(PowerState {
+        level: life.clamp(0, 100) as f32 / 100.,
+        state,
+        time_remaining: Some(time).filter(|&time| time > 0).map(|time| time as f32 * 60.),
+        energy_rate: None,
+    })
+}
+
+/// The battery as the APM emulation of OpenBSD and NetBSD sees it
+fn apm_state() -> anyhow::Result<PowerState> {
+    // Each flag is asked for separately, as the order they're printed in differs between systems
+    let query = |flag| run("apm", &[flag]).and_then(|output| parse_number("apm", Some(&output)));
+    let life = query("-l")?;
+    let battery_state = query("-b")?;
+    let ac = query("-a")?;
+
+    let state = match battery_state {
+        APM_BATTERY_ABSENT => ChargeState::Absent,
+        APM_BATTERY_CHARGING => ChargeState::Charging,
+        _ if ac == APM_AC_ONLINE => ChargeState::FullyCharged,
+        _ => ChargeState::Discharging,
+    };
+    // apm only estimates the time to empty, and says "unknown" when it can't
+    let time_remaining = if state.is_charging() {
+        None
+    } else {
+        query("-m").ok().filter(|&time| time > 0).map(|time| time as f32 * 60.)
+    };
+    Ok(PowerState {
+        level: life.clamp(0, 100) as f32 / 100.,
+        state,
+        time_remaining,
+        energy_rate: None,
+    })
+}
@@ -0,0 +1,1204 @@
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+use palette::convert::FromColorUnclamped;
+use palette::{Oklaba, Srgb};
+
+use crate::color::{parse_color, parse_color_option, UserColor};
+use crate::{ChargeState, Edge};
+
+const USAGE: &str = "\
+Usage: wattbar [OPTIONS]
+
+Options:
+    --size PIXELS           Thickness of the bar (default: 3)
+    --output-size NAME=PIXELS[,NAME=PIXELS...]
+                            Use a different thickness on the named outputs
+    --output-reverse NAME[,NAME...]
+                            Fill the bar from the other end on the named outputs, on top of
+                            any --reverse
+    --auto-size             Only ask for the thickness of the bar, and let the compositor decide
+                            its length
+    --length PERCENT        Only cover PERCENT of the edge, rather than all of it
+    --gravity start|center|end
+                            Where along the edge a --length bar sits: the left or top end, the
+                            middle, or the right or bottom end (default: center)
+    --border-all            Draw the bar as a frame around the whole screen, filling clockwise
+                            from the top left corner
+    --vertical-from-bottom  In --border-all, fill both side bars from the bottom up, rather than
+                            the right one downwards as clockwise would have it
+    --edge-size EDGE=PIXELS[,EDGE=PIXELS...]
+                            In --border-all, use a different thickness along the given edges
+                            (top, right, bottom or left), on every output
+    --icon WIDTHxHEIGHT     Draw a small battery icon of this size in a corner of the screen
+                            instead of a bar along its edge
+    --icon-corner top-left|top-right|bottom-left|bottom-right
+                            Which corner the icon sits in, --margin away from both edges
+                            (default: bottom-right)
+    --exclusive-edge top|right|bottom|left
+                            Reserve the space on this edge rather than the one the bar is
+                            drawn against; it must be one the bar touches. This also lets an
+                            --icon reserve space
+    --reserve PIXELS        Space to reserve for the bar at the edge of the screen
+                            (default: the same as --size)
+    --margin PIXELS         Distance between the bar and the edge of the screen (default: 0)
+    --no-reserve-margin     Don't include --margin in the reserved space, so that windows may
+                            fill the gap between the bar and the edge of the screen
+    --style solid|dots|drained
+                            How to draw the bar; dots remain legible on very thin bars, and
+                            drained colors the used part of the battery rather than what's left
+                            (default: dots if --size is below 3, otherwise solid)
+    --curve linear|log|perceptual
+                            How the battery level maps onto the length of the bar; log and
+                            perceptual give more room to a nearly empty battery
+                            (default: linear)
+    --quantize PERCENT      Round the battery level to a multiple of PERCENT, and only redraw
+                            when the rounded level changes
+    --reverse STATE[,STATE...]
+                            Fill the bar from the right rather than the left in each STATE
+                            (charging, full, discharging, empty, absent or all)
+    --theme default|cividis Colors to show the battery in: red through green, or the colorblind
+                            safe dark blue through yellow of cividis (default: default)
+    --track-color [STATE=]COLOR
+                            Draw the unfilled part of the bar in COLOR rather than a darker
+                            shade of the fill, either always or only in STATE (charging,
+                            full, discharging, empty or absent); may be given more than once
+    --zero-color COLOR      Draw the bar in COLOR when the battery is at exactly 0%
+    --full-color COLOR      Draw the bar in COLOR when the battery is at exactly 100%
+    --outline COLOR         Draw a one pixel border in COLOR around the bar
+    --fade-ends PIXELS      Fade the bar out over the last PIXELS pixels at each end
+    --shadow                Darken the rows along the bar's inner edge, to set it apart from
+                            whatever is beneath it on screen
+    --backdrop COLOR        Adjust the bar's lightness where needed to stand out against COLOR,
+                            such as the main color of the wallpaper behind it
+    --min-contrast RATIO    Adjust the lightness of the unfilled part of the bar until it has at
+                            least this WCAG contrast ratio (1 to 21) with the filled part
+    --accent-fallback COLOR Color to use for accent when the desktop doesn't provide an accent
+                            color (default: #0080ff)
+    --mono                  Draw in shades of gray, with brightness showing the battery level;
+                            combine with --style dots to also show the level as a pattern
+    --mono-levels LOW,HIGH  Lightness percentages of an empty and a full battery in --mono
+                            (default: 30,90)
+    --full-threshold PERCENT
+                            Treat a battery that is charging at PERCENT or more as full, for
+                            batteries that never quite report finishing
+    --critical-time MINUTES Color the bar as empty when less than MINUTES of discharge remain
+    --full-flash MS         Flash the bar white for MS milliseconds when charging completes
+    --secondary-battery PATH
+                            Also show the UPower device at the D-Bus object PATH (e.g.
+                            /org/freedesktop/UPower/devices/battery_BAT1) in a thinner strip
+                            along the bottom of the bar
+    --edge-battery EDGE=PATH
+                            Show the UPower device at PATH on the bar along EDGE (top, right,
+                            bottom or left) instead of the main battery; needs --border-all
+                            and the UPower backend, and may be given more than once
+    --sparkline MINUTES     Trace the battery level over the last MINUTES across bars at least
+                            4 pixels thick
+    --show-peak             Mark the highest level the battery has reached since it last
+                            changed between charging and discharging
+    --show-rate WATTS       Draw the charge/discharge rate along the top row of the bar,
+                            reaching the full width at WATTS
+    --namespace NAME        Layer shell namespace to give the bar, for matching compositor rules
+                            (default: WattBar)
+    --dim-on-idle SECONDS   Fade the bar down after SECONDS without user activity
+    --stale-after SECONDS   Wash the color out of a battery's part of the bar if no update for
+                            it has arrived for SECONDS; UPower only reports changes, so allow
+                            for a steady battery
+    --charging-shimmer      Run a highlight along the bar while the battery is charging
+    --charging-bubbles PIXELS
+                            Send bright bubbles PIXELS apart along the fill towards its end
+                            while the battery is charging
+    --bubble-speed PIXELS   Distance the --charging-bubbles travel each second (default: 20)
+    --charging-edge COLOR   Mark the end of the fill in COLOR while the battery is charging
+    --fullscreen-behavior raise|hide|ignore
+                            While a window is fullscreen on an output, move the bar above it,
+                            take the bar down, or leave it be (default: ignore)
+    --keyboard-interactive  Allow the compositor to give the bar keyboard focus. Otherwise the
+                            bar asks never to get it, so it can't steal focus on any layer
+    --startup-animation     Animate the bar while waiting for battery status, whether at
+                            startup or while reconnecting to UPower
+    --max-fps FPS           Limit animations to FPS frames per second (default: 30); they are
+                            never drawn faster than the display refreshes
+    --debug-frame-time MS   Log every frame that takes longer than MS milliseconds to draw
+    --backend upower|acpi   Read the battery status from UPower (the default), or from the
+                            ACPI battery sysctls or apm found on the BSDs
+    --upower-property FIELD=PROPERTY
+                            Read FIELD (level, state, time-to-full, time-to-empty or rate)
+                            from the UPower device property PROPERTY instead of the usual one;
+                            may be given more than once
+    --raw-max VALUE         Read the UPower level as a raw value that reaches VALUE when full,
+                            rather than as a percentage, e.g. with --upower-property
+                            level=Energy
+    --mock-upower           Display a simulated battery instead of querying UPower
+    --mock-speed SECONDS    Time taken by the mock battery to sweep from empty to full
+                            (default: 5.12)
+    --mock-charging         Show the mock battery as charging while it sweeps
+    --mock-bounce           Sweep the mock battery up while charging, then back down while
+                            discharging
+    --mock-tour             Take the mock battery through every charge state in turn, to see
+                            how the bar shows each of them
+    --mock-state LEVEL[:charging|:full|:discharging|:empty|:absent]
+                            Hold the mock battery at LEVEL percent instead of sweeping
+    --fixed-level LEVEL     Ignore the battery and always show it LEVEL percent full
+                            (default: 100)
+    --fixed-state STATE     Ignore the battery and always show it as charging, full,
+                            discharging, empty or absent (default: discharging)
+    --test-colors STATE     Show the colors for every level from 0% at one end to 100% at the
+                            other, as in STATE (charging, full, discharging or empty), with the
+                            track colors along the bottom, instead of the battery
+    --once                  Exit shortly after the bar has been drawn with the battery status,
+                            e.g. to take a screenshot of it
+    --require-battery       Exit quietly at startup if the system has no battery, e.g. to
+                            share one configuration between laptops and desktops
+    --print-config          Print the settings in effect, after defaults, as TOML and exit
+    --dry-run               Check the options and that battery information is available,
+                            then exit without displaying anything
+    -h, --help              Print this message and exit
+
+COLOR may be a hex code (#rgb or #rrggbb), a CSS color name, a mix of two colors such as
+\"color-mix(in oklab, red 40%, green)\", or accent to follow the desktop's accent color.
+";
+
+/// Bars thinner than this are drawn with [`Style::Dots`] unless a style is given explicitly
+const DOTS_THRESHOLD: u32 = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Style {
+    /// A continuous fill
+    Solid,
+    /// A row of discrete dots, lit in proportion to the battery level
+    Dots,
+    /// Like `Solid`, but with the color on the part of the battery that has been used
+    Drained,
+}
+
+impl FromStr for Style {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "solid" => Ok(Style::Solid),
+            "dots" => Ok(Style::Dots),
+            "drained" => Ok(Style::Drained),
+            _ => bail!("Unknown style {:?}", s),
+        }
+    }
+}
+
+/// Mapping from battery level to the displayed level
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Curve {
+    Linear,
+    /// Logarithmic, with 10% of the battery taking up about 28% of the bar
+    Log,
+    /// The CIE L* lightness curve, so that equal steps in level look like equal steps in brightness
+    Perceptual,
+}
+
+impl Curve {
+    /// Remap `level`, which should be between 0 and 1
+    pub fn apply(self, level: f32) -> f32 {
+        let level = level.clamp(0., 1.);
+        match self {
+            Curve::Linear => level,
+            Curve::Log => (1. + 9. * level).log10(),
+            Curve::Perceptual => {
+                const EPSILON: f32 = 216. / 24389.;
+                const KAPPA: f32 = 24389. / 27.;
+                if level > EPSILON {
+                    (116. * level.cbrt() - 16.) / 100.
+                } else {
+                    KAPPA * level / 100.
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Curve {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Curve::Linear),
+            "log" => Ok(Curve::Log),
+            "perceptual" => Ok(Curve::Perceptual),
+            _ => bail!("Unknown curve {:?}", s),
+        }
+    }
+}
+
+/// The set of colors the battery level is shown in
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Theme {
+    /// Red through green while discharging, and blue while charging
+    Default,
+    /// Dark blue through yellow while discharging, and sky blue while charging, which stay
+    /// distinguishable with the common color vision deficiencies
+    Cividis,
+}
+
+impl FromStr for Theme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Theme::Default),
+            "cividis" => Ok(Theme::Cividis),
+            _ => bail!("Unknown theme {:?}", s),
+        }
+    }
+}
+
+/// Where a bar shorter than its edge is placed along it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Gravity {
+    /// The left or top end
+    Start,
+    Center,
+    /// The right or bottom end
+    End,
+}
+
+impl FromStr for Gravity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "start" => Ok(Gravity::Start),
+            "center" => Ok(Gravity::Center),
+            "end" => Ok(Gravity::End),
+            _ => bail!("Unknown gravity {:?}", s),
+        }
+    }
+}
+
+/// A corner of the screen, for `--icon`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl FromStr for Corner {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top-left" => Ok(Corner::TopLeft),
+            "top-right" => Ok(Corner::TopRight),
+            "bottom-left" => Ok(Corner::BottomLeft),
+            "bottom-right" => Ok(Corner::BottomRight),
+            _ => bail!("Unknown corner {:?}", s),
+        }
+    }
+}
+
+/// What to do with the bar while a window is fullscreen on its output
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FullscreenBehavior {
+    /// Move the bar to the overlay layer, above the fullscreen window
+    Raise,
+    /// Unmap the bar until the window leaves fullscreen
+    Hide,
+    Ignore,
+}
+
+impl FromStr for FullscreenBehavior {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raise" => Ok(FullscreenBehavior::Raise),
+            "hide" => Ok(FullscreenBehavior::Hide),
+            "ignore" => Ok(FullscreenBehavior::Ignore),
+            _ => bail!("Unknown fullscreen behavior {:?}", s),
+        }
+    }
+}
+
+/// Where the battery status comes from
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Upower,
+    /// FreeBSD's ACPI battery sysctls, or `apm` on the other BSDs
+    Acpi,
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "upower" => Ok(Backend::Upower),
+            "acpi" => Ok(Backend::Acpi),
+            _ => bail!("Unknown backend {:?}", s),
+        }
+    }
+}
+
+/// Colors for the unfilled part of the bar, by charge state
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct TrackColors {
+    pub charging: Option<UserColor>,
+    pub fully_charged: Option<UserColor>,
+    pub discharging: Option<UserColor>,
+    pub empty: Option<UserColor>,
+    pub absent: Option<UserColor>,
+}
+
+impl TrackColors {
+    pub fn get(&self, state: ChargeState) -> Option<UserColor> {
+        match state {
+            ChargeState::Charging => self.charging,
+            ChargeState::FullyCharged => self.fully_charged,
+            ChargeState::Discharging => self.discharging,
+            // An empty battery is still discharging, unless told otherwise
+            ChargeState::Empty => self.empty.or(self.discharging),
+            ChargeState::Absent => self.absent,
+        }
+    }
+
+    /// Apply a `[STATE=]COLOR` specification from the command line
+    fn set(&mut self, flag: &str, spec: &str) -> anyhow::Result<()> {
+        match spec.split_once('=') {
+            Some((state, color)) => {
+                let color = Some(parse_color_option(flag, color)?);
+                match state.parse()? {
+                    ChargeState::Charging => self.charging = color,
+                    ChargeState::FullyCharged => self.fully_charged = color,
+                    ChargeState::Discharging => self.discharging = color,
+                    ChargeState::Empty => self.empty = color,
+                    ChargeState::Absent => self.absent = color,
+                }
+            }
+            None => {
+                let color = Some(parse_color_option(flag, spec)?);
+                *self = TrackColors {
+                    charging: color,
+                    fully_charged: color,
+                    discharging: color,
+                    empty: color,
+                    absent: color,
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Names of the UPower device properties that the battery status is read from
+#[derive(Clone, Debug, PartialEq)]
+pub struct PropertyNames {
+    /// Charge level, out of `level_max`
+    pub level: String,
+    /// Value of the level property when full: 100 for a percentage
+    pub level_max: f64,
+    /// Charge state, as a UPower battery state
+    pub state: String,
+    /// Seconds until fully charged
+    pub time_to_full: String,
+    /// Seconds until empty
+    pub time_to_empty: String,
+    /// Rate of charge or discharge in watts; optional
+    pub energy_rate: String,
+}
+
+impl Default for PropertyNames {
+    fn default() -> Self {
+        PropertyNames {
+            level: "Percentage".to_owned(),
+            level_max: 100.,
+            state: "State".to_owned(),
+            time_to_full: "TimeToFull".to_owned(),
+            time_to_empty: "TimeToEmpty".to_owned(),
+            energy_rate: "EnergyRate".to_owned(),
+        }
+    }
+}
+
+impl PropertyNames {
+    /// Apply a `FIELD=PROPERTY` specification from the command line
+    fn set(&mut self, flag: &str, spec: &str) -> anyhow::Result<()> {
+        let (field, name) = spec.split_once('=').ok_or_else(|| anyhow!("{} expects FIELD=PROPERTY", flag))?;
+        if name.is_empty() {
+            bail!("{} needs a property name for {}", flag, field);
+        }
+        let slot = match field {
+            "level" => &mut self.level,
+            "state" => &mut self.state,
+            "time-to-full" => &mut self.time_to_full,
+            "time-to-empty" => &mut self.time_to_empty,
+            "rate" => &mut self.energy_rate,
+            _ => bail!("Unknown field {:?} for {}", field, flag),
+        };
+        *slot = name.to_owned();
+        Ok(())
+    }
+}
+
+/// Lightness range used by `--mono`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MonoLevels {
+    /// Oklab lightness of an empty battery, between 0 and 1
+    pub low: f32,
+    /// Oklab lightness of a full battery, between 0 and 1
+    pub high: f32,
+}
+
+impl Default for MonoLevels {
+    fn default() -> Self {
+        MonoLevels { low: 0.3, high: 0.9 }
+    }
+}
+
+impl FromStr for MonoLevels {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (low, high) = s.split_once(',').ok_or_else(|| anyhow!("Expected LOW,HIGH"))?;
+        let parse = |level: &str| -> anyhow::Result<f32> {
+            let level: f32 = level
+                .trim()
+                .trim_end_matches('%')
+                .parse()
+                .with_context(|| format!("Invalid lightness {:?}", level))?;
+            if !(0. ..=100.).contains(&level) {
+                bail!("Lightness must be between 0 and 100");
+            }
+            Ok(level / 100.)
+        };
+        Ok(MonoLevels {
+            low: parse(low)?,
+            high: parse(high)?,
+        })
+    }
+}
+
+/// How the mock battery moves when it isn't held at a fixed state
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MockSweep {
+    /// From empty to full, over and over, while discharging
+    Discharging,
+    /// The same, but while charging
+    Charging,
+    /// Up while charging, then down while discharging
+    Bounce,
+    /// Through every charge state in turn
+    Tour,
+}
+
+/// Fixed state for the mock battery to display
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MockState {
+    /// Level, between 0 and 1
+    pub level: f32,
+    pub state: ChargeState,
+}
+
+impl FromStr for MockState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (level, state) = match s.split_once(':') {
+            Some((level, state)) => (level, state.parse()?),
+            None => (s, ChargeState::Discharging),
+        };
+        Ok(MockState {
+            level: parse_level(level)?,
+            state,
+        })
+    }
+}
+
+/// Parse a battery level given in percent, returning it between 0 and 1
+fn parse_level(level: &str) -> anyhow::Result<f32> {
+    let pct: f32 = level
+        .trim_end_matches('%')
+        .parse()
+        .with_context(|| format!("Invalid battery level {:?}", level))?;
+    if !(0. ..=100.).contains(&pct) {
+        bail!("Battery level must be between 0 and 100");
+    }
+    Ok(pct / 100.)
+}
+
+#[derive(Clone, Debug)]
+pub struct Cli {
+    /// Thickness of the bar, in logical pixels
+    pub size: u32,
+    /// Thickness overrides for particular outputs, by name
+    pub output_size: Vec<(String, u32)>,
+    /// Outputs on which the bar fills from the other end
+    pub output_reverse: Vec<String>,
+    /// Leave the length of the bar to the compositor rather than matching the output's mode
+    pub auto_size: bool,
+    /// If set, the fraction of the edge the bar covers
+    pub length: Option<f32>,
+    /// Where along the edge a bar with a `length` sits
+    pub gravity: Gravity,
+    /// If set, draw a battery icon of this size rather than a bar
+    pub icon: Option<(u32, u32)>,
+    /// The corner of the screen the icon sits in
+    pub icon_corner: Corner,
+    /// The edge to reserve space along, if not the one the bar is on
+    pub exclusive_edge: Option<Edge>,
+    /// Draw a bar on every edge, together making up a frame around the screen
+    pub border_all: bool,
+    /// Fill the side bars of the frame upwards
+    pub vertical_from_bottom: bool,
+    /// Thickness overrides for particular edges of the frame, which win over `output_size`
+    pub edge_size: Vec<(Edge, u32)>,
+    /// Exclusive zone to request; defaults to `size`
+    pub reserve: Option<u32>,
+    /// Distance between the bar and the edge of the screen
+    pub margin: u32,
+    /// Whether the exclusive zone covers the margin as well as the bar
+    pub reserve_margin: bool,
+    /// Rendering style; chosen based on `size` if not given
+    pub style: Option<Style>,
+    /// Mapping from battery level to the displayed level
+    pub curve: Curve,
+    pub theme: Theme,
+    /// If set, the battery level is rounded to a multiple of this, between 0 and 1
+    pub quantize: Option<f32>,
+    /// Charge states in which the bar fills from the other end
+    pub reverse: Vec<ChargeState>,
+    /// Colors of the unfilled part of the bar; a darker shade of the fill if unset
+    pub track_color: TrackColors,
+    /// Color of the whole bar at a level of exactly 0
+    pub zero_color: Option<UserColor>,
+    /// Color of the whole bar at a level of exactly 1
+    pub full_color: Option<UserColor>,
+    /// Color of a border drawn around the edge of the bar
+    pub outline: Option<UserColor>,
+    /// Length over which the ends of the bar fade to transparent
+    pub fade_ends: Option<u32>,
+    /// Shade the inner edge of the bar
+    pub shadow: bool,
+    /// What the bar is seen against, to keep its colors distinct from
+    pub backdrop: Option<UserColor>,
+    /// If set, the least contrast ratio between the filled and unfilled parts of the bar
+    pub min_contrast: Option<f32>,
+    /// Stands in for the accent color if the desktop doesn't report one
+    pub accent_fallback: Oklaba,
+    /// If set, draw in grayscale using these lightness levels
+    pub mono: Option<MonoLevels>,
+    /// If set, a charging battery at this level or above is treated as full
+    pub full_threshold: Option<f32>,
+    /// Seconds of remaining discharge below which the battery is drawn as empty
+    pub critical_time: Option<f32>,
+    /// How long to flash the bar when the battery finishes charging
+    pub full_flash: Option<Duration>,
+    /// D-Bus object paths of batteries to show on particular edges instead of the main one
+    pub edge_battery: Vec<(Edge, String)>,
+    /// D-Bus object path of a second battery to display alongside the main one
+    pub secondary_battery: Option<String>,
+    /// If set, how far back the sparkline of recent levels goes
+    pub sparkline: Option<Duration>,
+    /// Mark the highest level seen in the current charge state
+    pub show_peak: bool,
+    /// If set, draw the energy rate as a gauge with this full-scale value in watts
+    pub show_rate: Option<f32>,
+    /// Layer shell namespace of the bar's surfaces
+    pub namespace: String,
+    /// If set, dim the bar after the user has been idle this long
+    pub dim_on_idle: Option<Duration>,
+    /// If set, the bar is drawn as stale after this long without an update
+    pub stale_after: Option<Duration>,
+    /// Animate a highlight along the fill while charging
+    pub charging_shimmer: bool,
+    /// If set, the distance between bubbles rising along the fill while charging
+    pub charging_bubbles: Option<u32>,
+    /// Pixels per second travelled by the charging bubbles
+    pub bubble_speed: f32,
+    /// If set, the color of the leading edge of the fill while charging
+    pub charging_edge: Option<UserColor>,
+    /// What to do with the bar while a window is fullscreen on its output
+    pub fullscreen_behavior: FullscreenBehavior,
+    /// Accept keyboard focus rather than never taking it
+    pub keyboard_interactive: bool,
+    /// Animate the placeholder shown while there is no battery status
+    pub startup_animation: bool,
+    /// If set, the frame rate of animations in place of the default
+    pub max_fps: Option<u32>,
+    /// Log frames that take at least this long to draw
+    pub debug_frame_time: Option<Duration>,
+    /// Draw the colors for every level in this state instead of the battery status
+    pub test_colors: Option<ChargeState>,
+    /// Exit once every bar has been drawn with real battery status
+    pub once: bool,
+    /// Exit successfully at startup if there's no battery to show
+    pub require_battery: bool,
+    /// Only print the settings, then exit
+    pub print_config: bool,
+    /// Only validate the configuration and backend, then exit
+    pub dry_run: bool,
+    /// What to read the battery status from
+    pub backend: Backend,
+    /// Where in the UPower device's properties to find the battery status
+    pub properties: PropertyNames,
+    /// Use the simulated battery rather than UPower
+    pub mock_upower: bool,
+    /// Time for the mock battery to go from empty to full
+    pub mock_speed: Duration,
+    /// How the mock battery sweeps
+    pub mock_sweep: MockSweep,
+    /// If set, the mock battery holds this state rather than sweeping
+    pub mock_state: Option<MockState>,
+}
+
+impl Default for Cli {
+    fn default() -> Self {
+        Cli {
+            size: 3,
+            output_size: Vec::new(),
+            output_reverse: Vec::new(),
+            auto_size: false,
+            length: None,
+            gravity: Gravity::Center,
+            icon: None,
+            icon_corner: Corner::BottomRight,
+            exclusive_edge: None,
+            border_all: false,
+            vertical_from_bottom: false,
+            edge_size: Vec::new(),
+            reserve: None,
+            margin: 0,
+            reserve_margin: true,
+            style: None,
+            curve: Curve::Linear,
+            theme: Theme::Default,
+            quantize: None,
+            reverse: Vec::new(),
+            track_color: TrackColors::default(),
+            zero_color: None,
+            full_color: None,
+            outline: None,
+            fade_ends: None,
+            shadow: false,
+            backdrop: None,
+            min_contrast: None,
+            accent_fallback: Oklaba::from_color_unclamped(Srgb::new(0., 0.5, 1.)),
+            mono: None,
+            full_threshold: None,
+            critical_time: None,
+            full_flash: None,
+            edge_battery: Vec::new(),
+            secondary_battery: None,
+            sparkline: None,
+            show_peak: false,
+            show_rate: None,
+            namespace: "WattBar".to_owned(),
+            dim_on_idle: None,
+            stale_after: None,
+            charging_shimmer: false,
+            charging_bubbles: None,
+            bubble_speed: 20.,
+            charging_edge: None,
+            fullscreen_behavior: FullscreenBehavior::Ignore,
+            keyboard_interactive: false,
+            startup_animation: false,
+            max_fps: None,
+            debug_frame_time: None,
+            test_colors: None,
+            once: false,
+            require_battery: false,
+            print_config: false,
+            dry_run: false,
+            backend: Backend::Upower,
+            properties: PropertyNames::default(),
+            mock_upower: false,
+            mock_speed: Duration::from_millis(5120),
+            mock_sweep: MockSweep::Discharging,
+            mock_state: None,
+        }
+    }
+}
+
+impl Cli {
+    pub fn parse() -> anyhow::Result<Self> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    pub fn parse_from(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut cli = Cli::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            // Accept both "--flag value" and "--flag=value"
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_owned())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = || inline.clone().or_else(|| args.next()).ok_or_else(|| anyhow!("{} requires a value", flag));
+
+            match flag {
+                "--size" => {
+                    cli.size = parse_value(flag, &value()?)?;
+                    if cli.size == 0 {
+                        bail!("{} must be at least 1", flag);
+                    }
+                }
+                "--auto-size" => cli.auto_size = true,
+                "--border-all" => cli.border_all = true,
+                "--vertical-from-bottom" => cli.vertical_from_bottom = true,
+                "--edge-size" => {
+                    for entry in value()?.split(',') {
+                        let (edge, size) = entry
+                            .split_once('=')
+                            .ok_or_else(|| anyhow!("{} expects EDGE=PIXELS, not {:?}", flag, entry))?;
+                        let edge = parse_value(flag, edge)?;
+                        let size: u32 = parse_value(flag, size)?;
+                        if size == 0 {
+                            bail!("{} must be at least 1", flag);
+                        }
+                        cli.edge_size.retain(|(other, _)| *other != edge);
+                        cli.edge_size.push((edge, size));
+                    }
+                }
+                "--length" => {
+                    let length = parse_level(&value()?).with_context(|| format!("Invalid value for {}", flag))?;
+                    if length <= 0. {
+                        bail!("{} must be positive", flag);
+                    }
+                    cli.length = Some(length);
+                }
+                "--gravity" => cli.gravity = parse_value(flag, &value()?)?,
+                "--icon" => {
+                    let value = value()?;
+                    let (width, height) = value
+                        .split_once('x')
+                        .ok_or_else(|| anyhow!("{} takes a size such as 24x12", flag))?;
+                    let (width, height): (u32, u32) = (parse_value(flag, width)?, parse_value(flag, height)?);
+                    if width == 0 || height == 0 {
+                        bail!("{} must be at least 1x1", flag);
+                    }
+                    cli.icon = Some((width, height));
+                }
+                "--exclusive-edge" => cli.exclusive_edge = Some(parse_value(flag, &value()?)?),
+                "--icon-corner" => cli.icon_corner = parse_value(flag, &value()?)?,
+                "--margin" => cli.margin = parse_pixels(flag, &value()?)?,
+                "--no-reserve-margin" => cli.reserve_margin = false,
+                "--style" => cli.style = Some(parse_value(flag, &value()?)?),
+                "--output-size" => {
+                    for entry in value()?.split(',') {
+                        let (name, size) = entry
+                            .split_once('=')
+                            .ok_or_else(|| anyhow!("{} expects NAME=PIXELS, not {:?}", flag, entry))?;
+                        let size: u32 = parse_value(flag, size)?;
+                        if size == 0 {
+                            bail!("{} must be at least 1", flag);
+                        }
+                        cli.output_size.push((name.to_owned(), size));
+                    }
+                }
+                "--output-reverse" => cli.output_reverse.extend(value()?.split(',').map(str::to_owned)),
+                "--reserve" => cli.reserve = Some(parse_pixels(flag, &value()?)?),
+                "--curve" => cli.curve = parse_value(flag, &value()?)?,
+                "--theme" => cli.theme = parse_value(flag, &value()?)?,
+                "--quantize" => {
+                    let step = parse_level(&value()?).with_context(|| format!("Invalid value for {}", flag))?;
+                    if step <= 0. {
+                        bail!("{} must be positive", flag);
+                    }
+                    cli.quantize = Some(step);
+                }
+                "--reverse" => {
+                    for state in value()?.split(',') {
+                        match state {
+                            "all" => cli.reverse.extend([ChargeState::Charging, ChargeState::FullyCharged, ChargeState::Discharging, ChargeState::Empty, ChargeState::Absent]),
+                            _ => cli.reverse.push(state.parse().with_context(|| format!("Invalid state for {}", flag))?),
+                        }
+                    }
+                }
+                "--track-color" => cli.track_color.set(flag, &value()?)?,
+                "--zero-color" => cli.zero_color = Some(parse_color_option(flag, &value()?)?),
+                "--full-color" => cli.full_color = Some(parse_color_option(flag, &value()?)?),
+                "--shadow" => cli.shadow = true,
+                "--fade-ends" => cli.fade_ends = Some(parse_value(flag, &value()?)?).filter(|&pixels| pixels > 0),
+                "--outline" => cli.outline = Some(parse_color_option(flag, &value()?)?),
+                "--backdrop" => cli.backdrop = Some(parse_color_option(flag, &value()?)?),
+                "--min-contrast" => {
+                    let ratio: f32 = parse_value(flag, &value()?)?;
+                    if !(1. ..=21.).contains(&ratio) {
+                        bail!("{} must be between 1 and 21", flag);
+                    }
+                    cli.min_contrast = Some(ratio);
+                }
+                "--accent-fallback" => {
+                    let spec = value()?;
+                    cli.accent_fallback = parse_color(&spec).with_context(|| format!("Invalid color {:?} for {}", spec, flag))?;
+                }
+                "--mono" => {
+                    cli.mono.get_or_insert_with(MonoLevels::default);
+                }
+                "--mono-levels" => cli.mono = Some(parse_value(flag, &value()?)?),
+                "--full-threshold" => {
+                    cli.full_threshold = Some(parse_level(&value()?).with_context(|| format!("Invalid value for {}", flag))?);
+                }
+                "--critical-time" => {
+                    let minutes: f32 = parse_value(flag, &value()?)?;
+                    if minutes.is_nan() || minutes < 0. {
+                        bail!("{} must not be negative", flag);
+                    }
+                    cli.critical_time = Some(minutes * 60.);
+                }
+                "--full-flash" => cli.full_flash = Some(Duration::from_millis(parse_value(flag, &value()?)?)),
+                "--edge-battery" => {
+                    let value = value()?;
+                    let (edge, path) = value
+                        .split_once('=')
+                        .ok_or_else(|| anyhow!("{} expects EDGE=PATH, not {:?}", flag, value))?;
+                    let edge = parse_value(flag, edge)?;
+                    cli.edge_battery.retain(|(other, _)| *other != edge);
+                    cli.edge_battery.push((edge, path.to_owned()));
+                }
+                "--secondary-battery" => cli.secondary_battery = Some(value()?),
+                "--sparkline" => {
+                    let window = parse_duration(flag, &value()?, 60.)?;
+                    if window.is_zero() {
+                        bail!("{} must be positive", flag);
+                    }
+                    cli.sparkline = Some(window);
+                }
+                "--show-peak" => cli.show_peak = true,
+                "--show-rate" => {
+                    let max_rate: f32 = parse_value(flag, &value()?)?;
+                    if max_rate.is_nan() || max_rate <= 0. {
+                        bail!("{} must be positive", flag);
+                    }
+                    cli.show_rate = Some(max_rate);
+                }
+                "--namespace" => cli.namespace = value()?,
+                "--dim-on-idle" => {
+                    let timeout = parse_duration(flag, &value()?, 1.)?;
+                    // The compositor takes the timeout in milliseconds, as a u32
+                    if u32::try_from(timeout.as_millis()).is_err() {
+                        bail!("{} is too long", flag);
+                    }
+                    cli.dim_on_idle = Some(timeout);
+                }
+                "--stale-after" => {
+                    let stale_after = parse_duration(flag, &value()?, 1.)?;
+                    if stale_after.is_zero() {
+                        bail!("{} must be positive", flag);
+                    }
+                    cli.stale_after = Some(stale_after);
+                }
+                "--charging-shimmer" => cli.charging_shimmer = true,
+                "--charging-bubbles" => {
+                    let spacing: u32 = parse_value(flag, &value()?)?;
+                    if spacing == 0 {
+                        bail!("{} must be at least 1", flag);
+                    }
+                    cli.charging_bubbles = Some(spacing);
+                }
+                "--bubble-speed" => {
+                    cli.bubble_speed = parse_value(flag, &value()?)?;
+                    if cli.bubble_speed.is_nan() || cli.bubble_speed <= 0. {
+                        bail!("{} must be positive", flag);
+                    }
+                }
+                "--charging-edge" => cli.charging_edge = Some(parse_color_option(flag, &value()?)?),
+                "--fullscreen-behavior" => cli.fullscreen_behavior = parse_value(flag, &value()?)?,
+                "--keyboard-interactive" => cli.keyboard_interactive = true,
+                "--startup-animation" => cli.startup_animation = true,
+                "--max-fps" => {
+                    let fps = parse_value(flag, &value()?)?;
+                    if fps == 0 {
+                        bail!("{} must be at least 1", flag);
+                    }
+                    cli.max_fps = Some(fps);
+                }
+                "--debug-frame-time" => cli.debug_frame_time = Some(parse_duration(flag, &value()?, 0.001)?),
+                "--test-colors" => {
+                    let state = parse_value(flag, &value()?)?;
+                    cli.test_colors = Some(state);
+                    // The battery isn't shown, so there's no need to wait on it
+                    cli.mock_upower = true;
+                    cli.mock_state = Some(MockState { level: 1., state });
+                }
+                "--once" => cli.once = true,
+                "--require-battery" => cli.require_battery = true,
+                "--print-config" => cli.print_config = true,
+                "--dry-run" => cli.dry_run = true,
+                "--backend" => cli.backend = parse_value(flag, &value()?)?,
+                "--upower-property" => cli.properties.set(flag, &value()?)?,
+                "--raw-max" => {
+                    cli.properties.level_max = parse_value(flag, &value()?)?;
+                    if !(cli.properties.level_max > 0. && cli.properties.level_max.is_finite()) {
+                        bail!("{} must be positive", flag);
+                    }
+                }
+                "--mock-upower" => cli.mock_upower = true,
+                "--mock-speed" => {
+                    cli.mock_speed = parse_duration(flag, &value()?, 1.)?;
+                    if cli.mock_speed.is_zero() {
+                        bail!("{} must be positive", flag);
+                    }
+                    cli.mock_upower = true;
+                }
+                "--mock-charging" | "--mock-bounce" | "--mock-tour" => {
+                    cli.mock_sweep = match flag {
+                        "--mock-charging" => MockSweep::Charging,
+                        "--mock-bounce" => MockSweep::Bounce,
+                        _ => MockSweep::Tour,
+                    };
+                    cli.mock_upower = true;
+                }
+                "--mock-state" => {
+                    cli.mock_state = Some(parse_value(flag, &value()?)?);
+                    cli.mock_upower = true;
+                }
+                "--fixed-level" | "--fixed-state" => {
+                    let value = value()?;
+                    let fixed = cli.mock_state.get_or_insert(MockState { level: 1., state: ChargeState::Discharging });
+                    if flag == "--fixed-level" {
+                        fixed.level = parse_level(&value).with_context(|| format!("Invalid value for {}", flag))?;
+                    } else {
+                        fixed.state = parse_value(flag, &value)?;
+                    }
+                    cli.mock_upower = true;
+                }
+                "-h" | "--help" => {
+                    print!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => bail!("Unknown option {:?}; try --help", arg),
+            }
+        }
+
+        if cli.icon.is_some() && cli.border_all {
+            bail!("--icon can't be combined with --border-all");
+        }
+        // The corners of a frame need the bars to meet, and a length needs a size to take it from
+        if cli.length.is_some() && (cli.border_all || cli.auto_size) {
+            bail!("--length can't be combined with --border-all or --auto-size");
+        }
+        // Without a frame there's only the bottom bar, and --size already covers that
+        if !cli.edge_size.is_empty() && !cli.border_all {
+            bail!("--edge-size needs --border-all");
+        }
+        // Only a frame has side bars to fill upwards
+        if cli.vertical_from_bottom && !cli.border_all {
+            bail!("--vertical-from-bottom needs --border-all");
+        }
+        if !cli.edge_battery.is_empty() {
+            if !cli.border_all {
+                bail!("--edge-battery needs --border-all");
+            }
+            // The paths are UPower's, so there'd be nothing to show them with
+            if cli.mock_upower || cli.backend != Backend::Upower {
+                bail!("--edge-battery needs the UPower backend");
+            }
+        }
+        Ok(cli)
+    }
+
+    /// The thickness of the bar along `edge` of the named output, or of an output with no name
+    pub fn size_for(&self, output: Option<&str>, edge: Edge) -> u32 {
+        if let Some(&(_, size)) = self.edge_size.iter().find(|(other, _)| *other == edge) {
+            return size;
+        }
+        self.output_size
+            .iter()
+            .find(|(name, _)| Some(name.as_str()) == output)
+            .map_or(self.size, |&(_, size)| size)
+    }
+
+    /// The style for a bar of the given thickness
+    pub fn style(&self, size: u32) -> Style {
+        match self.style {
+            Some(style) => style,
+            None if size < DOTS_THRESHOLD => Style::Dots,
+            None => Style::Solid,
+        }
+    }
+
+    /// Whether the bar on the named output fills from the other end
+    pub fn reversed_on(&self, output: &str) -> bool {
+        self.output_reverse.iter().any(|name| name == output)
+    }
+
+    /// The space to reserve for a bar of the given thickness
+    pub fn reserve(&self, size: u32) -> u32 {
+        self.reserve.unwrap_or(size)
+    }
+
+    /// The exclusive zone to request. Layer shell counts this from the edge of the screen, so it
+    /// includes the margin unless asked not to. The protocol takes it as an i32, which it's
+    /// capped to fit.
+    pub fn exclusive_zone(&self, size: u32) -> i32 {
+        let zone = if self.reserve_margin {
+            self.reserve(size).saturating_add(self.margin)
+        } else {
+            self.reserve(size)
+        };
+        i32::try_from(zone).unwrap_or(i32::MAX)
+    }
+
+    /// Whether the bar fills from the other end in `state`
+    pub fn reversed(&self, state: ChargeState) -> bool {
+        self.reverse.contains(&state)
+    }
+
+    /// The settings in effect once defaults are filled in, as TOML. Every key is always present
+    /// apart from optional features that are off.
+    pub fn resolved_config(&self) -> String {
+        let mut lines = Vec::new();
+        let mut line = |key: &str, value: String| lines.push(format!("{} = {}", key, value));
+        let layout = match (self.icon, self.border_all) {
+            (Some(_), _) => "icon",
+            (None, true) => "frame",
+            (None, false) => "bar",
+        };
+        line("layout", toml_name(layout));
+        // wattbar falls back to a window only once it finds the compositor has no layer shell
+        line("layer", toml_name("bottom"));
+        line("namespace", toml_string(&self.namespace));
+        let backend = if self.mock_upower { "mock" } else { match self.backend { Backend::Upower => "upower", Backend::Acpi => "acpi" } };
+        line("backend", toml_name(backend));
+        if let Some(path) = &self.secondary_battery {
+            line("secondary-battery", toml_string(path));
+        }
+        line("theme", toml_name(self.theme));
+        line("curve", toml_name(self.curve));
+        line("margin", self.margin.to_string());
+        line("reserve-margin", self.reserve_margin.to_string());
+        line("fullscreen-behavior", toml_name(self.fullscreen_behavior));
+        if let Some(length) = self.length {
+            // Hundredths of a percent are as fine as anyone would give it
+            let percent = format!("{:.2}", length * 100.);
+            line("length", toml_string(&format!("{}%", percent.trim_end_matches('0').trim_end_matches('.'))));
+            line("gravity", toml_name(self.gravity));
+        }
+        let colors = [
+            ("outline", self.outline),
+            ("backdrop", self.backdrop),
+            ("charging-edge", self.charging_edge),
+            ("zero-color", self.zero_color),
+            ("full-color", self.full_color),
+            ("track-color.charging", self.track_color.charging),
+            ("track-color.full", self.track_color.fully_charged),
+            ("track-color.discharging", self.track_color.discharging),
+            ("track-color.empty", self.track_color.empty),
+            ("track-color.absent", self.track_color.absent),
+        ];
+        for (key, color) in colors {
+            if let Some(color) = color {
+                line(key, toml_string(&color.to_string()));
+            }
+        }
+        line("accent-fallback", toml_string(&UserColor::Fixed(self.accent_fallback).to_string()));
+
+        if let Some((width, height)) = self.icon {
+            lines.push(String::new());
+            lines.push("[icon]".to_owned());
+            lines.push(format!("width = {}", width));
+            lines.push(format!("height = {}", height));
+            lines.push(format!("corner = {}", toml_name(self.icon_corner)));
+        } else {
+            let edges: &[Edge] = if self.border_all { &Edge::CLOCKWISE } else { &[Edge::Bottom] };
+            for &edge in edges {
+                let size = self.size_for(None, edge);
+                lines.push(String::new());
+                lines.push(format!("[edge.{}]", toml_name(edge).trim_matches('"')));
+                lines.push(format!("size = {}", size));
+                lines.push(format!("exclusive-zone = {}", self.exclusive_zone(size)));
+                lines.push(format!("style = {}", toml_name(self.style(size))));
+                let battery = self.edge_battery.iter().find(|(other, _)| *other == edge).map_or("display", |(_, path)| path);
+                lines.push(format!("battery = {}", toml_string(battery)));
+            }
+        }
+        // Only edges without an --edge-size of their own change thickness per output
+        for (name, size) in &self.output_size {
+            lines.push(String::new());
+            lines.push(format!("[output.{}]", toml_string(name)));
+            lines.push(format!("size = {}", size));
+            lines.push(format!("style = {}", toml_name(self.style(*size))));
+            lines.push(format!("reversed = {}", self.reversed_on(name)));
+        }
+        for name in self.output_reverse.iter().filter(|name| !self.output_size.iter().any(|(other, _)| other == *name)) {
+            lines.push(String::new());
+            lines.push(format!("[output.{}]", toml_string(name)));
+            lines.push("reversed = true".to_owned());
+        }
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    /// Whether any color follows the desktop's accent color
+    pub fn uses_accent(&self) -> bool {
+        let colors = [self.outline, self.backdrop, self.charging_edge, self.zero_color, self.full_color, self.track_color.charging, self.track_color.fully_charged, self.track_color.discharging, self.track_color.empty, self.track_color.absent];
+        colors.contains(&Some(UserColor::Accent))
+    }
+}
+
+/// Quote the name of a setting's value for TOML, as it's spelt on the command line: `TopLeft`
+/// becomes `"top-left"`
+fn toml_name(value: impl std::fmt::Debug) -> String {
+    let debug = format!("{:?}", value);
+    let mut name = String::new();
+    for (i, c) in debug.trim_matches('"').chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            name.push('-');
+        }
+        name.push(c.to_ascii_lowercase());
+    }
+    toml_string(&name)
+}
+
+/// `value` as a TOML basic string. Debug formatting is close, but escapes differently, e.g.
+/// writing `\u{7f}` where TOML wants `\u007F`.
+fn toml_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\u{8}' => quoted.push_str("\\b"),
+            '\t' => quoted.push_str("\\t"),
+            '\n' => quoted.push_str("\\n"),
+            '\u{c}' => quoted.push_str("\\f"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Parse a distance on screen, which the protocol has to be able to carry as an i32
+fn parse_pixels(flag: &str, value: &str) -> anyhow::Result<u32> {
+    let pixels: u32 = parse_value(flag, value)?;
+    if i32::try_from(pixels).is_err() {
+        bail!("{} must be at most {}", flag, i32::MAX);
+    }
+    Ok(pixels)
+}
+
+/// Parse a length of time given in units of `unit` seconds, which mustn't be negative
+fn parse_duration(flag: &str, value: &str, unit: f32) -> anyhow::Result<Duration> {
+    let amount: f32 = parse_value(flag, value)?;
+    if amount.is_nan() || amount < 0. {
+        bail!("{} must not be negative", flag);
+    }
+    Duration::try_from_secs_f32(amount * unit).map_err(|_| anyhow!("{} is too long", flag))
+}
+
+fn parse_value<T>(flag: &str, value: &str) -> anyhow::Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|err| anyhow!("Invalid value {:?} for {}: {}", value, flag, err))
+}
@@ -0,0 +1,350 @@
+use std::fmt;
+
+use anyhow::{anyhow, bail, Context};
+use palette::convert::FromColorUnclamped;
+use palette::{LinSrgba, Mix, Oklaba, Srgb, Srgba};
+
+/// A color given by the user, which may follow the desktop's accent color
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UserColor {
+    Fixed(Oklaba),
+    /// The desktop's accent color, as reported by the appearance portal
+    Accent,
+}
+
+impl fmt::Display for UserColor {
+    /// Either `accent` or an `#rrggbb` code, as the color would be given on the command line
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UserColor::Fixed(color) => {
+                let rgb: Srgb<u8> = Srgb::from_color_unclamped(*color).into_format();
+                write!(f, "#{:02x}{:02x}{:02x}", rgb.red, rgb.green, rgb.blue)
+            }
+            UserColor::Accent => write!(f, "accent"),
+        }
+    }
+}
+
+impl UserColor {
+    /// Resolve to a concrete color, given the current accent color
+    pub fn resolve(self, accent: Oklaba) -> Oklaba {
+        match self {
+            UserColor::Fixed(color) => color,
+            UserColor::Accent => accent,
+        }
+    }
+}
+
+/// How far apart in lightness [`contrast_with`] keeps a color from its backdrop
+const MIN_CONTRAST: f32 = 0.25;
+
+/// Push `color`'s lightness away from `backdrop`'s, if needed to keep it visible against it.
+/// The color moves to whichever side of the backdrop has more room.
+pub fn contrast_with(color: Oklaba, backdrop: Oklaba) -> Oklaba {
+    if (color.l - backdrop.l).abs() >= MIN_CONTRAST {
+        return color;
+    }
+    let l = if backdrop.l > 0.5 {
+        (backdrop.l - MIN_CONTRAST).max(0.)
+    } else {
+        (backdrop.l + MIN_CONTRAST).min(1.)
+    };
+    Oklaba::new(l, color.a, color.b, color.alpha)
+}
+
+/// WCAG relative luminance of `color`
+fn luminance(color: Oklaba) -> f32 {
+    let rgb = LinSrgba::from_color_unclamped(color);
+    0.2126 * rgb.red.clamp(0., 1.) + 0.7152 * rgb.green.clamp(0., 1.) + 0.0722 * rgb.blue.clamp(0., 1.)
+}
+
+/// WCAG contrast ratio between two colors, from 1 (identical) to 21 (black on white)
+pub fn contrast_ratio(a: Oklaba, b: Oklaba) -> f32 {
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// Step size of the lightness search in [`with_contrast`]
+const CONTRAST_STEP: f32 = 0.02;
+
+/// Move `color`'s lightness away from `other` until the two have a contrast ratio of at least
+/// `ratio`. It goes darker if `other` is the lighter of the two, and lighter if not, then the other
+/// way if that runs out of room; if neither gets there, whichever came closer wins.
+pub fn with_contrast(color: Oklaba, other: Oklaba, ratio: f32) -> Oklaba {
+    if contrast_ratio(color, other) >= ratio {
+        return color;
+    }
+    let search = |direction: f32| {
+        let mut l = color.l;
+        loop {
+            let candidate = Oklaba::new(l, color.a, color.b, color.alpha);
+            if contrast_ratio(candidate, other) >= ratio || !(0. ..=1.).contains(&(l + direction)) {
+                return candidate;
+            }
+            l += direction;
+        }
+    };
+    let towards = if luminance(other) > luminance(color) { -CONTRAST_STEP } else { CONTRAST_STEP };
+    let first = search(towards);
+    if contrast_ratio(first, other) >= ratio {
+        return first;
+    }
+    let second = search(-towards);
+    if contrast_ratio(second, other) > contrast_ratio(first, other) {
+        second
+    } else {
+        first
+    }
+}
+
+/// Stops of the cividis colormap in sRGB, evenly spaced from 0 to 1. It runs from dark blue to
+/// yellow with lightness rising steadily, so it reads the same with the common color vision
+/// deficiencies as without.
+const CIVIDIS: [(f32, f32, f32); 5] = [
+    (0.000, 0.135, 0.305),
+    (0.208, 0.271, 0.424),
+    (0.486, 0.482, 0.471),
+    (0.737, 0.686, 0.435),
+    (0.996, 0.910, 0.220),
+];
+
+/// The color `level` of the way along the cividis colormap, mixed between stops in Oklab
+pub fn cividis(level: f32) -> Oklaba {
+    let position = level.clamp(0., 1.) * (CIVIDIS.len() - 1) as f32;
+    let index = (position as usize).min(CIVIDIS.len() - 2);
+    let stop = |(red, green, blue)| Oklaba::from_color_unclamped(Srgba::new(red, green, blue, 1.));
+    stop(CIVIDIS[index]).mix(&stop(CIVIDIS[index + 1]), position - index as f32)
+}
+
+/// Parse a color given by the user, either as a hex code (`#rgb` or `#rrggbb`), as a CSS
+/// color name, or as a CSS `color-mix()` of other colors.
+pub fn parse_color(spec: &str) -> anyhow::Result<Oklaba> {
+    let spec = spec.trim();
+    if let Some(args) = spec.strip_prefix("color-mix(").and_then(|rest| rest.strip_suffix(')')) {
+        return parse_color_mix(args).context("Invalid color-mix()");
+    }
+    let rgb: Srgb<u8> = if spec.starts_with('#') {
+        spec.parse().map_err(|err| anyhow!("{}", err))?
+    } else {
+        palette::named::from_str(&spec.to_ascii_lowercase()).ok_or_else(|| anyhow!("Unknown color name"))?
+    };
+    Ok(Oklaba::from_color_unclamped(rgb.into_format::<f32>()))
+}
+
+/// Evaluate the arguments of `color-mix(in SPACE, COLOR [P%], COLOR [P%])`
+fn parse_color_mix(args: &str) -> anyhow::Result<Oklaba> {
+    let args = split_top_level(args);
+    let (space, first, second) = match args.as_slice() {
+        [space, first, second] => (space, first, second),
+        _ => bail!("Expected a color space and two colors"),
+    };
+    let space = space.strip_prefix("in ").ok_or_else(|| anyhow!("Expected \"in\" and a color space"))?.trim();
+    let (first, first_weight) = parse_mix_color(first)?;
+    let (second, second_weight) = parse_mix_color(second)?;
+
+    // As in CSS, a missing percentage makes up the rest of 100%, and the two are scaled to add
+    // up to 100% if they don't already
+    let (first_weight, second_weight) = match (first_weight, second_weight) {
+        (None, None) => (0.5, 0.5),
+        (Some(first), None) => (first, 1. - first),
+        (None, Some(second)) => (1. - second, second),
+        (Some(first), Some(second)) => (first, second),
+    };
+    let total = first_weight + second_weight;
+    if total <= 0. {
+        bail!("The percentages must not both be zero");
+    }
+    let amount = second_weight / total;
+
+    Ok(match space.to_ascii_lowercase().as_str() {
+        "oklab" => first.mix(&second, amount),
+        "srgb" => {
+            // palette only mixes linear RGB, so gamma-encoded sRGB is mixed by hand
+            let (first, second) = (Srgba::from_color_unclamped(first), Srgba::from_color_unclamped(second));
+            let mix = |from: f32, to: f32| from + (to - from) * amount;
+            Oklaba::from_color_unclamped(Srgba::new(
+                mix(first.red, second.red),
+                mix(first.green, second.green),
+                mix(first.blue, second.blue),
+                mix(first.alpha, second.alpha),
+            ))
+        }
+        "srgb-linear" => {
+            let (first, second) = (LinSrgba::from_color_unclamped(first), LinSrgba::from_color_unclamped(second));
+            Oklaba::from_color_unclamped(first.mix(&second, amount))
+        }
+        _ => bail!("Unsupported color space {:?}; use oklab, srgb or srgb-linear", space),
+    })
+}
+
+/// Parse one `COLOR [P%]` argument of `color-mix()`, returning the weight as a fraction
+fn parse_mix_color(arg: &str) -> anyhow::Result<(Oklaba, Option<f32>)> {
+    let arg = arg.trim();
+    let (color, weight) = match arg.rsplit_once(' ') {
+        Some((color, weight)) if weight.ends_with('%') => (color, Some(weight)),
+        _ => (arg, None),
+    };
+    let weight = match weight {
+        Some(weight) => {
+            let percent: f32 = weight.trim_end_matches('%').parse().with_context(|| format!("Invalid percentage {:?}", weight))?;
+            if !(0. ..=100.).contains(&percent) {
+                bail!("Percentages must be between 0% and 100%");
+            }
+            Some(percent / 100.)
+        }
+        None => None,
+    };
+    Ok((parse_color(color)?, weight))
+}
+
+/// Split on the commas that aren't inside parentheses, so that mixes can be nested
+fn split_top_level(args: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in args.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(args[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(args[start..].trim());
+    parts
+}
+
+/// Parse a color for a command line option, naming the option in any error. Unlike
+/// [`parse_color`], this also accepts `accent`.
+pub fn parse_color_option(flag: &str, spec: &str) -> anyhow::Result<UserColor> {
+    if spec.eq_ignore_ascii_case("accent") {
+        return Ok(UserColor::Accent);
+    }
+    let color = parse_color(spec).with_context(|| format!("Invalid color {:?} for {}", spec, flag))?;
+    Ok(UserColor::Fixed(color))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Oklaba, expected: Oklaba) {
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+        assert!(
+            close(actual.l, expected.l) && close(actual.a, expected.a) && close(actual.b, expected.b) && close(actual.alpha, expected.alpha),
+            "{:?} is not {:?}",
+            actual,
+            expected,
+        );
+    }
+
+    fn color(spec: &str) -> Oklaba {
+        parse_color(spec).unwrap()
+    }
+
+    #[test]
+    fn splits_only_top_level_commas() {
+        assert_eq!(
+            split_top_level("in oklab, color-mix(in srgb, red, blue) 30%, white"),
+            ["in oklab", "color-mix(in srgb, red, blue) 30%", "white"],
+        );
+        assert_eq!(split_top_level("red"), ["red"]);
+    }
+
+    #[test]
+    fn mixes_in_each_space() {
+        let (red, blue) = (color("red"), color("blue"));
+        assert_close(color("color-mix(in oklab, red, blue)"), red.mix(&blue, 0.5));
+        assert_close(color("color-mix(in srgb, red, blue)"), Oklaba::from_color_unclamped(Srgba::new(0.5, 0., 0.5, 1.)));
+        let linear = LinSrgba::from_color_unclamped(red).mix(&LinSrgba::from_color_unclamped(blue), 0.5);
+        assert_close(color("color-mix(in srgb-linear, red, blue)"), Oklaba::from_color_unclamped(linear));
+        assert_close(color("color-mix(in OKLab, red 25%, blue 75%)"), red.mix(&blue, 0.75));
+    }
+
+    #[test]
+    fn mixes_nest() {
+        let purple = color("color-mix(in oklab, red, blue)");
+        assert_close(color("color-mix(in oklab, color-mix(in oklab, red, blue), white 20%)"), purple.mix(&color("white"), 0.2));
+    }
+
+    #[test]
+    fn a_missing_weight_makes_up_the_rest() {
+        assert_close(color("color-mix(in oklab, red 30%, blue)"), color("color-mix(in oklab, red 30%, blue 70%)"));
+        assert_close(color("color-mix(in oklab, red, blue 30%)"), color("color-mix(in oklab, red 70%, blue 30%)"));
+    }
+
+    #[test]
+    fn weights_are_scaled_to_add_up() {
+        let even = color("color-mix(in oklab, red, blue)");
+        assert_close(color("color-mix(in oklab, red 20%, blue 20%)"), even);
+        assert_close(color("color-mix(in oklab, red 80%, blue 80%)"), even);
+        assert_close(color("color-mix(in oklab, red 10%, blue 30%)"), color("color-mix(in oklab, red 25%, blue 75%)"));
+        assert!(parse_color("color-mix(in oklab, red 0%, blue 0%)").is_err());
+    }
+
+    #[test]
+    fn malformed_mixes_are_rejected() {
+        for spec in [
+            "color-mix(in oklab, red)",
+            "color-mix(in oklab, red, blue, white)",
+            "color-mix(oklab, red, blue)",
+            "color-mix(in xyz, red, blue)",
+            "color-mix(in oklab, red 150%, blue)",
+            "color-mix(in oklab, red -5%, blue)",
+            "color-mix(in oklab, red x%, blue)",
+            "color-mix(in oklab, reddish, blue)",
+            "color-mix(in oklab, red, blue",
+            "color-mix(in oklab, color-mix(in oklab, red, blue, white)",
+        ] {
+            assert!(parse_color(spec).is_err(), "{}", spec);
+        }
+    }
+
+    fn gray(l: f32) -> Oklaba {
+        Oklaba::new(l, 0., 0., 1.)
+    }
+
+    #[test]
+    fn contrast_ratio_runs_from_1_to_21() {
+        let (black, white) = (color("black"), color("white"));
+        assert!((contrast_ratio(black, white) - 21.).abs() < 0.01);
+        assert_eq!(contrast_ratio(white, black), contrast_ratio(black, white));
+        assert_eq!(contrast_ratio(gray(0.4), gray(0.4)), 1.);
+    }
+
+    #[test]
+    fn with_contrast_reaches_the_ratio() {
+        for ratio in [1.5, 3., 4.5, 7.] {
+            for (color, other) in [(gray(0.3), gray(0.4)), (gray(0.6), gray(0.7)), (color("navy"), color("skyblue")), (gray(0.5), color("gold"))] {
+                let adjusted = with_contrast(color, other, ratio);
+                assert!(contrast_ratio(adjusted, other) >= ratio, "{:?} against {:?} for {}", adjusted, other, ratio);
+                // Only the lightness moves
+                assert_eq!((adjusted.a, adjusted.b, adjusted.alpha), (color.a, color.b, color.alpha));
+            }
+        }
+    }
+
+    #[test]
+    fn with_contrast_leaves_enough_alone() {
+        let (black, white) = (color("black"), color("white"));
+        assert_eq!(with_contrast(black, white, 7.), black);
+    }
+
+    #[test]
+    fn with_contrast_gets_as_close_as_it_can() {
+        // Even black or white is well short of 21:1 against mid-gray
+        let backdrop = gray(0.6);
+        let best = contrast_ratio(gray(0.), backdrop).max(contrast_ratio(gray(1.), backdrop));
+        assert!(best < 21.);
+        for start in [0.2, 0.55, 0.65, 0.9] {
+            let adjusted = with_contrast(gray(start), backdrop, 21.);
+            let ratio = contrast_ratio(adjusted, backdrop);
+            assert!(ratio < 21.);
+            // Within a step of whichever extreme has more contrast
+            assert!(ratio > best * 0.9, "{} from {} against a best of {}", ratio, start, best);
+        }
+    }
+}
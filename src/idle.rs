@@ -0,0 +1,19 @@
+//! Bindings for the ext-idle-notify-v1 protocol, generated by `build.rs`
+
+pub mod client {
+    #![allow(dead_code, non_camel_case_types, unused_unsafe, unused_variables)]
+    #![allow(non_upper_case_globals, non_snake_case, unused_imports)]
+    #![allow(missing_docs, clippy::all)]
+    // Newer compilers object to how the generated code refers to its interface statics
+    #![allow(unknown_lints, static_mut_refs)]
+
+    pub(crate) use wayland_client::protocol::wl_seat;
+    pub(crate) use wayland_client::sys;
+    pub(crate) use wayland_client::{AnonymousObject, Attached, Main, Proxy, ProxyMap};
+    pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+    pub(crate) use wayland_commons::smallvec;
+    pub(crate) use wayland_commons::wire::{Argument, ArgumentType, Message, MessageDesc};
+    pub(crate) use wayland_commons::{Interface, MessageGroup};
+
+    include!(concat!(env!("OUT_DIR"), "/ext-idle-notify-v1_client_api.rs"));
+}
@@ -0,0 +1,21 @@
+//! Bindings for version 5 of the wlr-layer-shell-unstable-v1 protocol, generated by `build.rs`.
+//! wayland-protocols only goes up to version 4, which can't set the exclusive edge.
+
+pub mod client {
+    #![allow(dead_code, non_camel_case_types, unused_unsafe, unused_variables)]
+    #![allow(non_upper_case_globals, non_snake_case, unused_imports)]
+    #![allow(missing_docs, clippy::all)]
+    // Newer compilers object to how the generated code refers to its interface statics
+    #![allow(unknown_lints, static_mut_refs)]
+
+    pub(crate) use wayland_client::protocol::{wl_output, wl_surface};
+    pub(crate) use wayland_client::sys;
+    pub(crate) use wayland_client::{AnonymousObject, Attached, Main, Proxy, ProxyMap};
+    pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+    pub(crate) use wayland_commons::smallvec;
+    pub(crate) use wayland_commons::wire::{Argument, ArgumentType, Message, MessageDesc};
+    pub(crate) use wayland_commons::{Interface, MessageGroup};
+    pub(crate) use wayland_protocols::xdg_shell::client::xdg_popup;
+
+    include!(concat!(env!("OUT_DIR"), "/wlr-layer-shell-unstable-v1_client_api.rs"));
+}
@@ -1,232 +1,1697 @@
 extern crate core;
+// For the generated layer shell bindings
+#[macro_use]
+extern crate bitflags;
 
+pub mod acpi;
+pub mod cli;
+pub mod color;
+pub mod idle;
+pub mod layer_shell;
+pub mod portal;
+pub mod timers;
 pub mod upower;
 
 use std::cell::Cell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
 use std::sync::RwLock;
+use std::time::{Duration, Instant};
 use std::{cell::RefCell, rc::Rc, sync::Arc};
+use anyhow::Context;
+use upower::WatcherHealth;
+use cli::{Backend, Cli, Corner, FullscreenBehavior, Gravity, Style, Theme};
+use timers::{TimerPurpose, Timers};
 use palette::convert::FromColorUnclamped;
 use palette::{FromColor, LinSrgba, Mix, Oklaba, Shade, Srgba};
 use wayland_client::{
     protocol::{wl_output::WlOutput, wl_shm, wl_surface::WlSurface},
-    Attached, Main,
+    Attached, Display, Main,
+};
+use idle::client::ext_idle_notification_v1::{self, ExtIdleNotificationV1};
+use idle::client::ext_idle_notifier_v1::ExtIdleNotifierV1;
+use wayland_protocols::unstable::xdg_output::v1::client::zxdg_output_manager_v1::ZxdgOutputManagerV1;
+use wayland_protocols::wlr::unstable::foreign_toplevel::v1::client::{
+    zwlr_foreign_toplevel_handle_v1,
+    zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1},
 };
 
-use wayland_protocols::wlr::unstable::layer_shell::v1::client::{
+use layer_shell::client::{
     zwlr_layer_shell_v1::{self, ZwlrLayerShellV1},
     zwlr_layer_surface_v1::{self, ZwlrLayerSurfaceV1},
 };
 
 use smithay_client_toolkit::{
-    default_environment, environment::SimpleGlobal, new_default_environment,
-    output::with_output_info, output::OutputInfo, shm::AutoMemPool, WaylandSource,
+    data_device::DataDeviceHandler, default_environment, environment::Environment, environment::SimpleGlobal,
+    primary_selection::PrimarySelectionHandler, seat::SeatHandler,
+    shell::{self, ShellHandler, ShellSurface},
+    output::with_output_info, output::OutputInfo, output::XdgOutputHandler, shm::AutoMemPool, shm::ShmHandler,
+    WaylandSource,
 };
 use smithay_client_toolkit::output::Mode;
 
+/// How long an output's mode must stay the same before the bar is resized to match it
+const RESIZE_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Time between frames of an animation, unless `--max-fps` says otherwise
+const ANIMATION_FRAME: Duration = Duration::from_millis(33);
+
+/// Period of the `--startup-animation` breathing effect
+const BREATH_PERIOD: Duration = Duration::from_secs(2);
+
+/// Width of the window used in place of a bar when the output's size isn't known
+const FALLBACK_WINDOW_WIDTH: u32 = 640;
+
+/// How long `--once` leaves the bar on screen after drawing it
+const ONCE_LINGER: Duration = Duration::from_secs(1);
+
+/// Time taken by the `--charging-shimmer` highlight to travel the length of the fill
+const SHIMMER_PERIOD: Duration = Duration::from_secs(2);
+
+/// Length, in pixels, of the `--charging-edge` mark
+const CHARGING_EDGE: usize = 3;
+
+/// How much `--dim-on-idle` darkens the bar
+const IDLE_DIMMING: f32 = 0.6;
+
+/// How long `--dim-on-idle` takes to fade the bar in or out
+const IDLE_FADE: Duration = Duration::from_millis(500);
+
+/// Width of a single dot in [`Style::Dots`], including the gap that follows it
+const DOT_PITCH: usize = 3;
+
+/// Number of `--charging-bubbles` before their pattern of rows repeats
+const BUBBLE_CYCLE: i64 = 64;
+
+/// Rows of `--shadow` along the inner edge, and how dark the innermost of them gets
+const SHADOW_ROWS: usize = 2;
+const SHADOW_OPACITY: f32 = 0.5;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChargeState {
+    Charging,
+    FullyCharged,
+    Discharging,
+    /// Out of charge, and about to shut down
+    Empty,
+    /// There's no battery at all, e.g. because it has been removed
+    Absent,
+}
+
+impl ChargeState {
+    /// True if line power is available.
+    pub fn is_charging(self) -> bool {
+        match self {
+            // Without a battery, something else must be powering us
+            ChargeState::Charging | ChargeState::FullyCharged | ChargeState::Absent => true,
+            ChargeState::Discharging | ChargeState::Empty => false,
+        }
+    }
+}
+
+impl FromStr for ChargeState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "charging" => Ok(ChargeState::Charging),
+            "full" => Ok(ChargeState::FullyCharged),
+            "discharging" => Ok(ChargeState::Discharging),
+            "empty" => Ok(ChargeState::Empty),
+            "absent" => Ok(ChargeState::Absent),
+            _ => anyhow::bail!("Unknown charge state {:?}", s),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct PowerState {
     /// Level, between 0 and 1
     level: f32,
-    /// True if line power is available.
-    charging: bool,
-    /// Time to full charge/empty, in seconds
-    #[allow(unused)] // TODO: actually use this to display the time remaining
-    time_remaining: f32,
+    state: ChargeState,
+    /// Time to full charge/empty, in seconds, if the device has worked it out
+    time_remaining: Option<f32>,
+    /// Rate of charge (positive) or discharge (negative), in watts, if the device reports it
+    energy_rate: Option<f32>,
+}
+
+impl PowerState {
+    /// Settle a battery that says it's charging at `full_threshold` or above as fully charged
+    fn reconciled(mut self, full_threshold: Option<f32>) -> Self {
+        if matches!(full_threshold, Some(threshold) if self.state == ChargeState::Charging && self.level >= threshold) {
+            self.state = ChargeState::FullyCharged;
+        }
+        self
+    }
+
+    /// Round the level to a multiple of `step`, if given
+    fn quantized(mut self, step: Option<f32>) -> Self {
+        if let Some(step) = step {
+            self.level = ((self.level / step).round() * step).min(1.);
+        }
+        self
+    }
+}
+
+/// A battery's state and the highest level it's reached since entering it
+type Peak = Option<(ChargeState, f32)>;
+
+/// The parts of each battery's status that show on the bar, and the accent color, which
+/// `--quantize` compares to skip updates that wouldn't change anything
+type Appearance = (Vec<Option<(ChargeState, f32, Option<f32>, bool)>>, Option<Oklaba>);
+
+/// Whether the user has been away for `--dim-on-idle`, and since when
+#[derive(Copy, Clone, Debug)]
+pub struct Idleness {
+    idle: bool,
+    since: Instant,
 }
 
-#[derive(Default, Clone)]
+impl Idleness {
+    /// How far to darken the bar, fading towards [`IDLE_DIMMING`] while idle and back to 0 after
+    fn dimming(self) -> f32 {
+        let progress = (self.since.elapsed().as_secs_f32() / IDLE_FADE.as_secs_f32()).min(1.);
+        IDLE_DIMMING * if self.idle { progress } else { 1. - progress }
+    }
+
+    fn fading(self) -> bool {
+        self.since.elapsed() < IDLE_FADE
+    }
+}
+
+#[derive(Clone)]
 pub struct AppState {
     display_status: Arc<RwLock<Option<PowerState>>>,
+    /// Status of the batteries given by `--edge-battery`, by the edge they're shown on
+    edge_status: Vec<(Edge, Arc<RwLock<Option<PowerState>>>)>,
+    /// Status of the battery given by `--secondary-battery`, if any
+    secondary_status: Option<Arc<RwLock<Option<PowerState>>>>,
+    /// The desktop's accent color, if it has one
+    accent: Arc<RwLock<Option<Oklaba>>>,
+    /// Whether the user is away, if we've been told either way
+    idleness: Rc<Cell<Option<Idleness>>>,
+    /// Global ids of the outputs showing a fullscreen window
+    fullscreen_outputs: Rc<RefCell<HashSet<u32>>>,
+    /// How the main battery's watcher is doing
+    display_health: Arc<WatcherHealth>,
+    /// How the watchers of the `--edge-battery` batteries are doing, by edge
+    edge_health: Vec<(Edge, Arc<WatcherHealth>)>,
+    /// How the `--secondary-battery` watcher is doing, if there is one
+    secondary_health: Option<Arc<WatcherHealth>>,
+    /// The highest level shown since the main battery entered its current state, for
+    /// `--show-peak`
+    display_peak: Rc<Cell<Peak>>,
+    /// The same for the `--edge-battery` batteries, by edge
+    edge_peak: Vec<(Edge, Rc<Cell<Peak>>)>,
+    /// Recent levels of the main battery, oldest first, for `--sparkline`
+    history: Rc<RefCell<VecDeque<(Instant, f32)>>>,
+    cli: Arc<Cli>,
+    timers: Rc<Timers>,
+    /// When wattbar started, as a reference for animations
+    started: Instant,
 }
 
 default_environment! {
     MyEnv,
+    desktop,
     fields = [
         layer_shell: SimpleGlobal<ZwlrLayerShellV1>,
+        xdg_output: XdgOutputHandler,
+        idle_notifier: SimpleGlobal<ExtIdleNotifierV1>,
     ],
     singles = [
         ZwlrLayerShellV1 => layer_shell,
+        ZxdgOutputManagerV1 => xdg_output,
+        ExtIdleNotifierV1 => idle_notifier,
     ],
 }
 
-#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+/// Connect to the compositor. This is what `new_default_environment!` does, except that the
+/// output handler is paired with an xdg-output handler, which is where output names such as
+/// `DP-1` come from.
+fn connect() -> anyhow::Result<(Environment<MyEnv>, Display, wayland_client::EventQueue)> {
+    let display = Display::connect_to_env()?;
+    let mut queue = display.create_event_queue();
+    let (sctk_outputs, xdg_output) = XdgOutputHandler::new_output_handlers();
+    let mut sctk_seats = SeatHandler::new();
+    let sctk_data_device_manager = DataDeviceHandler::init(&mut sctk_seats);
+    let sctk_primary_selection_manager = PrimarySelectionHandler::init(&mut sctk_seats);
+    let env = Environment::new(&display.attach(queue.token()), &mut queue, MyEnv {
+        sctk_compositor: SimpleGlobal::new(),
+        sctk_subcompositor: SimpleGlobal::new(),
+        sctk_shm: ShmHandler::new(),
+        sctk_outputs,
+        sctk_seats,
+        sctk_data_device_manager,
+        sctk_primary_selection_manager,
+        sctk_shell: ShellHandler::new(),
+        sctk_decoration_mgr: SimpleGlobal::new(),
+        layer_shell: SimpleGlobal::new(),
+        xdg_output,
+        idle_notifier: SimpleGlobal::new(),
+    })?;
+    Ok((env, display, queue))
+}
+
+/// A screen edge that a bar can sit against
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Edge {
+    Top,
+    Right,
+    Bottom,
+    Left,
+}
+
+impl FromStr for Edge {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top" => Ok(Edge::Top),
+            "right" => Ok(Edge::Right),
+            "bottom" => Ok(Edge::Bottom),
+            "left" => Ok(Edge::Left),
+            _ => anyhow::bail!("Unknown edge {:?}", s),
+        }
+    }
+}
+
+impl Edge {
+    /// The order in which `--border-all` fills the edges, clockwise from the top left corner
+    const CLOCKWISE: [Edge; 4] = [Edge::Top, Edge::Right, Edge::Bottom, Edge::Left];
+
+    fn is_vertical(self) -> bool {
+        matches!(self, Edge::Left | Edge::Right)
+    }
+
+    /// This edge alone, as an anchor
+    fn side(self) -> zwlr_layer_surface_v1::Anchor {
+        use zwlr_layer_surface_v1::Anchor;
+        match self {
+            Edge::Top => Anchor::Top,
+            Edge::Right => Anchor::Right,
+            Edge::Bottom => Anchor::Bottom,
+            Edge::Left => Anchor::Left,
+        }
+    }
+
+    /// Anchor to this edge and both the ones perpendicular to it, so that the bar spans the
+    /// whole edge rather than relying on the compositor to stretch it
+    fn anchor(self) -> zwlr_layer_surface_v1::Anchor {
+        use zwlr_layer_surface_v1::Anchor;
+        match self {
+            Edge::Top => Anchor::Top | Anchor::Left | Anchor::Right,
+            Edge::Right => Anchor::Right | Anchor::Top | Anchor::Bottom,
+            Edge::Bottom => Anchor::Bottom | Anchor::Left | Anchor::Right,
+            Edge::Left => Anchor::Left | Anchor::Top | Anchor::Bottom,
+        }
+    }
+
+    /// Anchor to this edge, and to the end of it given by `gravity`, for a bar that doesn't span
+    /// the whole edge. A centered bar is anchored to this edge alone, which the compositor
+    /// centers along it.
+    fn partial_anchor(self, gravity: Gravity) -> zwlr_layer_surface_v1::Anchor {
+        use zwlr_layer_surface_v1::Anchor;
+        let end = match (gravity, self.is_vertical()) {
+            (Gravity::Start, false) => Anchor::Left,
+            (Gravity::End, false) => Anchor::Right,
+            (Gravity::Start, true) => Anchor::Top,
+            (Gravity::End, true) => Anchor::Bottom,
+            (Gravity::Center, _) => Anchor::empty(),
+        };
+        self.side() | end
+    }
+
+    /// Map pixel `(x, y)` of a bar drawn horizontally, `length` by `thickness`, with its last row
+    /// against the screen edge, onto a surface on this edge. If `clockwise`, x runs clockwise
+    /// around the screen; otherwise it runs left to right or top to bottom.
+    fn to_surface(self, (x, y): (usize, usize), (length, thickness): (usize, usize), clockwise: bool) -> (usize, usize) {
+        match self {
+            Edge::Top => (x, thickness - 1 - y),
+            Edge::Right => (y, x),
+            Edge::Bottom if clockwise => (length - 1 - x, y),
+            Edge::Bottom => (x, y),
+            Edge::Left if clockwise => (thickness - 1 - y, length - 1 - x),
+            Edge::Left => (thickness - 1 - y, x),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub enum RenderEvent {
     Closed,
     Configure { width: u32, height: u32 },
     DataChanged,
 }
 
+/// What the bars' surfaces are placed on screen with
+#[derive(Clone)]
+pub enum Shell {
+    Layer(Attached<ZwlrLayerShellV1>),
+    /// Plain windows, for compositors that don't support layer shell
+    Window(Environment<MyEnv>),
+}
+
+/// The role given to a bar's surface by its [`Shell`]
+pub enum Role {
+    Layer(Main<ZwlrLayerSurfaceV1>),
+    /// A window can't be placed along an edge or reserve space, so it's only a best effort.
+    /// Always `Some` until the surface is dropped.
+    Window(Option<Box<dyn ShellSurface>>),
+}
+
 pub struct Surface {
     surface: WlSurface,
     output: WlOutput,
-    layer_surface: Main<ZwlrLayerSurfaceV1>,
+    role: Role,
     next_render_event: Rc<Cell<Option<RenderEvent>>>,
     pool: AutoMemPool,
+    /// The bar drawn horizontally, before being turned to fit `edge`
+    scratch: Vec<u8>,
+    edge: Edge,
     mode: Option<Mode>,
     scale: i32,
+    /// Global id of our output
+    output_id: u32,
+    /// If set, the bar is drawn as a solid flash
+    flashing: bool,
+    /// Whether the last frame committed showed real battery status, rather than a placeholder
+    showing_status: bool,
     dimensions: (u32, u32),
+    /// Thickness of the bar on this output
+    size: u32,
+    /// Whether everything is flipped end to end on this output
+    reversed: bool,
+    style: Style,
     display_status: Arc<RwLock<Option<PowerState>>>,
+    secondary_status: Option<Arc<RwLock<Option<PowerState>>>>,
+    accent: Arc<RwLock<Option<Oklaba>>>,
+    idleness: Rc<Cell<Option<Idleness>>>,
+    fullscreen_outputs: Rc<RefCell<HashSet<u32>>>,
+    /// Whether the bar is currently making way for a fullscreen window
+    covered: bool,
+    /// Whether the bar has been taken down for `--fullscreen-behavior hide`
+    hidden: bool,
+    /// The highest level shown since the battery entered its current state, for `--show-peak`
+    peak: Rc<Cell<Peak>>,
+    /// How the watchers of the battery shown and of the secondary battery are doing
+    health: Arc<WatcherHealth>,
+    secondary_health: Option<Arc<WatcherHealth>>,
+    history: Rc<RefCell<VecDeque<(Instant, f32)>>>,
+    cli: Arc<Cli>,
+    timers: Rc<Timers>,
+    started: Instant,
 }
 
 impl Surface {
     fn new(
         output: &WlOutput,
         surface: WlSurface,
-        layer_shell: &Attached<ZwlrLayerShellV1>,
+        shell: &Shell,
         pool: AutoMemPool,
 	    state: &AppState,
+        edge: Edge,
     ) -> Self {
-        let layer_surface: Main<ZwlrLayerSurfaceV1> = layer_shell.get_layer_surface(
-            &surface,
-            Some(output),
-            zwlr_layer_shell_v1::Layer::Bottom,
-            "WattBar".to_owned(),
-        );
-
-
-        layer_surface.set_anchor(zwlr_layer_surface_v1::Anchor::Bottom);
+        let (output_id, size, reversed) = match with_output_info(output, |info| (info.id, output_name(info))) {
+            Some((id, name)) => (id, state.cli.size_for(Some(&name), edge), state.cli.reversed_on(&name)),
+            None => (0, state.cli.size_for(None, edge), false),
+        };
         let next_render_event = Rc::new(Cell::new(None));
         let nre_handle = Rc::clone(&next_render_event);
+        let role = match shell {
+            Shell::Layer(layer_shell) => {
+                let layer_surface: Main<ZwlrLayerSurfaceV1> = layer_shell.get_layer_surface(
+                    &surface,
+                    Some(output),
+                    zwlr_layer_shell_v1::Layer::Bottom,
+                    state.cli.namespace.clone(),
+                );
 
-        layer_surface.quick_assign(move |layer_surface, event, _| {
-            match (event, nre_handle.get()) {
-                (zwlr_layer_surface_v1::Event::Closed, _) => {
-                    nre_handle.set(Some(RenderEvent::Closed));
+                let anchor = match (state.cli.icon, state.cli.length) {
+                    (Some(_), _) => corner_anchor(state.cli.icon_corner),
+                    (None, Some(_)) => edge.partial_anchor(state.cli.gravity),
+                    (None, None) => edge.anchor(),
+                };
+                layer_surface.set_anchor(anchor);
+                if let Some(exclusive_edge) = state.cli.exclusive_edge {
+                    if layer_surface.as_ref().version() < 5 {
+                        eprintln!("The compositor doesn't support choosing the exclusive edge; ignoring --exclusive-edge");
+                    } else if !anchor.contains(exclusive_edge.side()) {
+                        eprintln!("The bar isn't against the {:?} edge, so it can't reserve space there; ignoring --exclusive-edge", exclusive_edge);
+                    } else {
+                        layer_surface.set_exclusive_edge(exclusive_edge.side());
+                    }
+                } else if state.cli.length.is_some() && state.cli.gravity != Gravity::Center && layer_surface.as_ref().version() >= 5 {
+                    // Anchored in a corner, the compositor can't tell which edge to reserve space
+                    // on by itself. Older ones just won't reserve any.
+                    layer_surface.set_exclusive_edge(edge.side());
                 }
-                (
-                    zwlr_layer_surface_v1::Event::Configure {
-                        serial,
-                        width,
-                        height,
-                    },
-                    next,
-                ) if next != Some(RenderEvent::Closed) => {
-                    layer_surface.ack_configure(serial);
-                    nre_handle.set(Some(RenderEvent::Configure { width, height }));
-                }
-                (_, _) => {}
+                // The protocol's default is not to take focus, but it costs nothing to be sure
+                layer_surface.set_keyboard_interactivity(keyboard_interactivity(&state.cli, layer_surface.as_ref().version()));
+
+                layer_surface.quick_assign(move |layer_surface, event, _| {
+                    match (event, nre_handle.get()) {
+                        (zwlr_layer_surface_v1::Event::Closed, _) => {
+                            nre_handle.set(Some(RenderEvent::Closed));
+                        }
+                        (
+                            zwlr_layer_surface_v1::Event::Configure {
+                                serial,
+                                width,
+                                height,
+                            },
+                            next,
+                        ) if next != Some(RenderEvent::Closed) => {
+                            layer_surface.ack_configure(serial);
+                            nre_handle.set(Some(RenderEvent::Configure { width, height }));
+                        }
+                        (_, _) => {}
+                    }
+                });
+                Role::Layer(layer_surface)
             }
-        });
+            Shell::Window(env) => {
+                let window = env.create_shell_surface(&surface, move |event, _| {
+                    match (event, nre_handle.get()) {
+                        (shell::Event::Close, _) => {
+                            nre_handle.set(Some(RenderEvent::Closed));
+                        }
+                        // A size of zero leaves the choice to us
+                        (shell::Event::Configure { new_size, .. }, next) if next != Some(RenderEvent::Closed) => {
+                            let (width, height) = new_size.unwrap_or((0, 0));
+                            nre_handle.set(Some(RenderEvent::Configure { width, height }));
+                        }
+                        (_, _) => {}
+                    }
+                });
+                window.set_title("WattBar".to_owned());
+                window.set_app_id("wattbar".to_owned());
+                Role::Window(Some(window))
+            }
+        };
 
         let mut result = Surface {
             surface,
             output: output.clone(),
-            layer_surface,
+            role,
             next_render_event,
             mode: None,
             scale: 1,
+            output_id,
+            flashing: false,
+            showing_status: false,
             pool,
+            scratch: Vec::new(),
+            edge,
             dimensions: (0, 0),
-            display_status: Arc::clone(&state.display_status),
+            size,
+            reversed,
+            style: state.cli.style(size),
+            display_status: match state.edge_status.iter().find(|(other, _)| *other == edge) {
+                Some((_, status)) => Arc::clone(status),
+                None => Arc::clone(&state.display_status),
+            },
+            secondary_status: state.secondary_status.clone(),
+            accent: Arc::clone(&state.accent),
+            idleness: Rc::clone(&state.idleness),
+            fullscreen_outputs: Rc::clone(&state.fullscreen_outputs),
+            covered: false,
+            hidden: false,
+            peak: match state.edge_peak.iter().find(|(other, _)| *other == edge) {
+                Some((_, peak)) => Rc::clone(peak),
+                None => Rc::clone(&state.display_peak),
+            },
+            health: match state.edge_health.iter().find(|(other, _)| *other == edge) {
+                Some((_, health)) => Arc::clone(health),
+                None => Arc::clone(&state.display_health),
+            },
+            secondary_health: state.secondary_health.clone(),
+            history: Rc::clone(&state.history),
+            cli: Arc::clone(&state.cli),
+            timers: Rc::clone(&state.timers),
+            started: state.started,
         };
-        result.resize();
+        if let Some((mode, scale)) = result.output_mode() {
+            result.mode = mode;
+            result.scale = scale;
+        }
+        result.apply_size();
         result.surface.commit();
 
         result
     }
 
-    fn resize(&mut self) {
+    /// The current mode and scale factor of our output
+    fn output_mode(&self) -> Option<(Option<Mode>, i32)> {
         with_output_info(&self.output, |info| {
-            let mode = info.modes.iter().find(|mode| (*mode).is_current).cloned();
-            if self.mode.map(|mode| mode.dimensions) == mode.map(|mode| mode.dimensions) && self.scale == info.scale_factor {
+            let mode = info.modes.iter().find(|mode| mode.is_current).cloned();
+            (mode, output_scale(info.scale_factor))
+        })
+    }
+
+    /// Watch for changes to the output's mode or scale. Changes tend to come in bursts (e.g., while
+    /// the compositor is reconfiguring outputs), so the bar is only resized once they settle down
+    fn resize(&mut self) {
+        if let Some((mode, scale)) = self.output_mode() {
+            if self.mode.map(|mode| mode.dimensions) != mode.map(|mode| mode.dimensions) || self.scale != scale {
+                // eprintln!("Output mode: {:?}, scale: {}", mode, scale);
+                self.mode = mode;
+                self.scale = scale;
+                self.timers.schedule(TimerPurpose::Resize(self.output_id, self.edge), RESIZE_DEBOUNCE);
+            }
+        }
+    }
+
+    /// The size to ask for, where 0 lets the compositor choose
+    fn requested_size(&self) -> (u32, u32) {
+        Self::size_request(&self.cli, self.edge, self.size, self.mode.map(|mode| mode.dimensions), self.scale)
+    }
+
+    /// The size to ask for a bar `size` thick along `edge`, on an output whose current mode, if
+    /// known, is `dimensions` device pixels at `scale`
+    fn size_request(cli: &Cli, edge: Edge, size: u32, dimensions: Option<(i32, i32)>, scale: i32) -> (u32, u32) {
+        if let Some(size) = cli.icon {
+            size
+        } else if let Some(length) = cli.length {
+            // Without both ends anchored, the compositor can't stretch us, so there has to be a
+            // length even before the mode is known
+            let edge_length = match dimensions {
+                Some(dimensions) if edge.is_vertical() => (dimensions.1 / scale) as u32,
+                Some(dimensions) => (dimensions.0 / scale) as u32,
+                None => FALLBACK_WINDOW_WIDTH,
+            };
+            let length = ((edge_length as f32 * length).round() as u32).max(1);
+            if edge.is_vertical() {
+                (size, length)
+            } else {
+                (length, size)
+            }
+        } else if edge.is_vertical() {
+            // Left and right bars fit between the top and bottom ones, so the compositor is left
+            // to stretch them between whatever space those reserve
+            (size, 0)
+        } else {
+            // A width of 0 lets the compositor stretch us between the anchored edges, which is the
+            // best we can do without a mode, and what --auto-size asks for
+            let width = match dimensions {
+                Some(dimensions) if !cli.auto_size => (dimensions.0 / scale) as u32,
+                _ => 0,
+            };
+            (width, size)
+        }
+    }
+
+    /// The size the bar is drawn at, in logical pixels. That's whatever the compositor last
+    /// configured, which it's free to pick over what we asked for, so anything reserved has to go
+    /// by this rather than the request.
+    fn drawn_size(&self) -> (u32, u32) {
+        Self::drawn(self.requested_size(), self.dimensions)
+    }
+
+    /// The size a bar that asked for `requested` is drawn at once configured to `configured`,
+    /// where 0 is left to us
+    fn drawn((requested_width, requested_height): (u32, u32), (width, height): (u32, u32)) -> (u32, u32) {
+        (if width == 0 { requested_width } else { width }, if height == 0 { requested_height } else { height })
+    }
+
+    /// Reserve space for the bar as drawn, so windows stop exactly where it ends at any scale
+    fn apply_exclusive_zone(&self, layer_surface: &zwlr_layer_surface_v1::ZwlrLayerSurfaceV1) {
+        layer_surface.set_exclusive_zone(exclusive_zone(&self.cli, self.edge, self.drawn_size()));
+    }
+
+    fn apply_size(&self) {
+        let (width, height) = self.requested_size();
+        let layer_surface = match &self.role {
+            Role::Layer(layer_surface) => layer_surface,
+            Role::Window(window) => {
+                // Pinning the size is as close as a window can get to hugging the edge
+                let width = if width == 0 { FALLBACK_WINDOW_WIDTH } else { width };
+                if let Some(window) = window {
+                    window.set_min_size(Some((width as i32, height as i32)));
+                    window.set_max_size(Some((width as i32, height as i32)));
+                }
                 return;
             }
-            // eprintln!("Output {} mode: {:?}, scale: {}", info.name, mode, info.scale_factor);
-            if let Some(mode) = mode {
-                self.layer_surface.set_size((mode.dimensions.0 / info.scale_factor) as u32, 3);
-                self.layer_surface.set_exclusive_zone(3);
-                self.scale = info.scale_factor;
+        };
+        let margin = self.cli.margin as i32;
+        layer_surface.set_size(width, height);
+        if self.cli.icon.is_some() {
+            match self.cli.icon_corner {
+                Corner::TopLeft => layer_surface.set_margin(margin, 0, 0, margin),
+                Corner::TopRight => layer_surface.set_margin(margin, margin, 0, 0),
+                Corner::BottomLeft => layer_surface.set_margin(0, 0, margin, margin),
+                Corner::BottomRight => layer_surface.set_margin(0, margin, margin, 0),
             }
-        });
+        } else {
+            match self.edge {
+                Edge::Top => layer_surface.set_margin(margin, 0, 0, 0),
+                Edge::Right => layer_surface.set_margin(0, margin, 0, 0),
+                Edge::Bottom => layer_surface.set_margin(0, 0, margin, 0),
+                Edge::Left => layer_surface.set_margin(0, 0, 0, margin),
+            }
+        }
+        self.apply_exclusive_zone(layer_surface);
+        if self.cli.border_all && self.edge == Edge::Top {
+            if let Some(mode) = self.mode {
+                // Checked once per output, and again whenever it changes mode
+                if self.reserved_across() >= mode.dimensions.1 / self.scale {
+                    eprintln!("The top and bottom of the frame are thicker than the screen is tall, leaving no room for the sides");
+                }
+            }
+        }
+    }
 
+    /// The space reserved by the top and bottom bars of the frame on this output together
+    fn reserved_across(&self) -> i32 {
+        let name = with_output_info(&self.output, output_name);
+        let reserved = |edge| self.cli.exclusive_zone(self.cli.size_for(name.as_deref(), edge));
+        reserved(Edge::Top).saturating_add(reserved(Edge::Bottom))
+    }
+
+    /// The part of the screen's perimeter covered by this bar in `--border-all`, as the fractions
+    /// of the perimeter before it and along it
+    fn segment(&self) -> (f32, f32) {
+        let index = Edge::CLOCKWISE.iter().position(|&edge| edge == self.edge).unwrap_or(0);
+        let quarter = (index as f32 / 4., 0.25);
+        let (width, height) = match self.mode {
+            Some(mode) => (mode.dimensions.0 / self.scale, mode.dimensions.1 / self.scale),
+            None => return quarter,
+        };
+        // The sides lose the space reserved by the top and bottom bars
+        let side = (height - self.reserved_across()).max(0);
+        let lengths = [width, side, width, side];
+        let perimeter: i32 = lengths.iter().sum();
+        if perimeter <= 0 {
+            return quarter;
+        }
+        let before: i32 = lengths[..index].iter().sum();
+        (before as f32 / perimeter as f32, lengths[index] as f32 / perimeter as f32)
+    }
+
+    /// Request a redraw, unless something more important is already pending
+    fn data_changed(&self) {
+        if self.next_render_event.get().is_none() {
+            self.next_render_event.set(Some(RenderEvent::DataChanged));
+        }
+    }
+
+    /// Get out of the way of a fullscreen window on our output, or come back once it's gone
+    fn make_way(&mut self) {
+        let covered = self.fullscreen_outputs.borrow().contains(&self.output_id);
+        if covered == self.covered {
+            return;
+        }
+        self.covered = covered;
+        let layer_surface = match &self.role {
+            Role::Layer(layer_surface) => layer_surface,
+            // A window is a toplevel itself, and gets stacked by the compositor like any other
+            Role::Window(_) => return,
+        };
+        match self.cli.fullscreen_behavior {
+            FullscreenBehavior::Raise if layer_surface.as_ref().version() >= 2 => {
+                let layer = if covered { zwlr_layer_shell_v1::Layer::Overlay } else { zwlr_layer_shell_v1::Layer::Bottom };
+                layer_surface.set_layer(layer);
+                self.surface.commit();
+            }
+            FullscreenBehavior::Hide if covered => {
+                // Committing without a buffer unmaps the surface
+                self.hidden = true;
+                self.surface.attach(None, 0, 0);
+                self.surface.commit();
+            }
+            FullscreenBehavior::Hide => {
+                // Mapping it again starts over, with a commit to get configured and then a buffer
+                self.hidden = false;
+                self.dimensions = (0, 0);
+                self.apply_size();
+                self.surface.commit();
+            }
+            FullscreenBehavior::Raise | FullscreenBehavior::Ignore => {}
+        }
     }
 
     fn handle_events(&mut self) -> bool {
         self.resize(); // There's probably a better way of doing this, but this isn't going to cost too much
+        self.make_way();
         match self.next_render_event.take() {
             Some(RenderEvent::Closed) => true,
-            Some(RenderEvent::Configure { width, height }) => {
+            Some(event @ RenderEvent::Configure { width, height }) => {
+                // Only windows are offered a size of zero, in which case it's ours to pick
+                let (width, height) = match (width, height, &self.role) {
+                    (0, _, Role::Window(_)) | (_, 0, Role::Window(_)) => {
+                        let (requested_width, requested_height) = self.requested_size();
+                        let requested_width = if requested_width == 0 { FALLBACK_WINDOW_WIDTH } else { requested_width };
+                        Self::drawn((requested_width, requested_height), (width, height))
+                    }
+                    _ => (width, height),
+                };
                 if self.dimensions != (width, height) {
                     self.dimensions = (width, height);
-                    self.draw();
+                    if let Role::Layer(layer_surface) = &self.role {
+                        // Goes out with the commit of the frame drawn at the new size
+                        self.apply_exclusive_zone(layer_surface);
+                    }
+                    self.draw(event);
                 }
                 false
             }
-	    Some(RenderEvent::DataChanged) => {
-		self.draw();
+	    Some(event @ RenderEvent::DataChanged) => {
+		self.draw(event);
 		false
 	    }
             None => false,
         }
     }
 
-    fn draw(&mut self) {
-        if self.dimensions.0 == 0 || self.dimensions.1 == 0 {
-            return;
-        }
-        let stride = 4 * self.dimensions.0 as i32;
-        let width = self.dimensions.0 as i32;
-        let height = self.dimensions.1 as i32;
-
-        let (canvas, buffer) = self
-            .pool
-            .buffer(width, height, stride, wl_shm::Format::Argb8888)
-            .unwrap();
+    fn read_status(cli: &Cli, status: &RwLock<Option<PowerState>>) -> Option<PowerState> {
+        status.read().map_or(None, |lock| *lock).map(|state| {
+            let mut state = state.reconciled(cli.full_threshold).quantized(cli.quantize);
+            state.level = cli.curve.apply(state.level);
+            state
+        })
+    }
 
-        let state = self.display_status.read().map_or(None, |lock| lock.clone());
+    /// The colors of the filled and unfilled parts of the bar, and how much of it to fill
+    fn colors(cli: &Cli, accent: Oklaba, state: Option<PowerState>) -> (Oklaba, Oklaba, f32) {
+        let (fg, bg, pct) = if let Some(PowerState { state: ChargeState::Absent, .. }) = state {
+            // Nothing to show the level of, so the whole bar is a gray track
+            let fg = Oklaba::new(0.6, 0., 0., 1.);
+            let bg = cli.track_color.get(ChargeState::Absent).map_or(Oklaba::new(0.3, 0., 0., 1.), |color| color.resolve(accent));
+            (fg, bg, 0.)
+        } else if let Some(state) = state {
+            // Running out of time is shown as though the battery were already empty, however
+            // much charge is reported. So is a battery UPower says is empty, in case the
+            // percentage hasn't caught up.
+            let critical = state.state == ChargeState::Empty || match cli.critical_time {
+                Some(threshold) => !state.state.is_charging() && matches!(state.time_remaining, Some(time) if time < threshold),
+                None => false,
+            };
+            let color_level = if critical { 0. } else { state.level };
 
-        let (base_color, pct) = if let Some(state) = state {
-            let mix_color = if !state.charging {
+            let mix_color = if let Some(mono) = cli.mono {
+                // Brightness alone carries the level, so the bar stays readable without hue
+                let lightness = if state.state.is_charging() { mono.high } else { mono.low + (mono.high - mono.low) * color_level };
+                Oklaba::new(lightness, 0., 0., 1.)
+            } else if state.state.is_charging() {
+                charging_color(cli.theme)
+            } else if cli.theme == Theme::Cividis {
+                color::cividis(color_level)
+            } else {
                 let min_color = Oklaba::from_color_unclamped(palette::LinSrgba::new(1., 0., 0., 1.));
                 let max_color = Oklaba::from_color_unclamped(palette::LinSrgba::new(0., 1., 0., 1.));
-                min_color.mix(&max_color, state.level)
-            } else {
-                Oklaba::from_color_unclamped(Srgba::new(0., 0.5, 1., 1.0f32))
+                min_color.mix(&max_color, color_level)
             };
 
-            (mix_color, state.level)
+            let track_color = match cli.track_color.get(state.state) {
+                Some(color) => color.resolve(accent),
+                None => mix_color.darken(0.5),
+            };
+            // An empty bar is all track and a full one is all fill, so that's where the colors
+            // for those two levels go
+            match (cli.zero_color, cli.full_color) {
+                (Some(color), _) if state.level <= 0. => (mix_color, color.resolve(accent), state.level),
+                (_, Some(color)) if state.level >= 1. => (color.resolve(accent), track_color, state.level),
+                _ => (mix_color, track_color, state.level),
+            }
         } else {
-            let color = Oklaba::from_color_unclamped(Srgba::new(0., 0.5, 1., 1.0f32));
+            let color = match cli.mono {
+                Some(mono) => Oklaba::new((mono.low + mono.high) / 2., 0., 0., 1.),
+                None => charging_color(cli.theme),
+            };
             let pct = 0.5;
-            (color, pct)
+            (color, color.darken(0.5), pct)
         };
 
-        let bg_color = base_color.darken(0.5);
+        let (fg, bg) = match cli.backdrop {
+            Some(backdrop) => {
+                let backdrop = backdrop.resolve(accent);
+                (color::contrast_with(fg, backdrop), color::contrast_with(bg, backdrop))
+            }
+            None => (fg, bg),
+        };
+        match cli.min_contrast {
+            Some(ratio) => (fg, color::with_contrast(bg, fg, ratio), pct),
+            None => (fg, bg, pct),
+        }
+    }
+
+    /// How much of a bar covering `segment` of the perimeter to fill, for a battery `level` full
+    fn segment_level((start, length): (f32, f32), level: f32) -> f32 {
+        if length <= 0. {
+            return 0.;
+        }
+        ((level - start) / length).clamp(0., 1.)
+    }
+
+    /// Where `segment` lies when the bar fills from the other end
+    fn directed((start, length): (f32, f32), reverse: bool) -> (f32, f32) {
+        if reverse {
+            (1. - start - length, length)
+        } else {
+            (start, length)
+        }
+    }
+
+    /// Fill `rows` with a row of crosses in `fg` on `bg`, to show that there's no battery
+    fn fill_absent(rows: &mut [u8], shape: (usize, usize), (fg, bg, _): (Oklaba, Oklaba, f32)) {
+        let fg_color = to_argb(fg);
+        let bg_color = to_argb(bg);
+        let height = row_count(rows, shape);
+        for (y, row) in pixel_rows(rows, shape).enumerate() {
+            for (x, chunk) in row.chunks_exact_mut(4).enumerate() {
+                // Each cross fills a square as tall as the bar, with a gap column after it
+                let column = x % (height + 1);
+                let on_cross = column == y || column + y + 1 == height;
+                chunk.copy_from_slice(if on_cross { fg_color.as_slice() } else { bg_color.as_slice() });
+            }
+        }
+    }
+
+    /// Draw bubbles `spacing` pixels apart on the filled part of `rows`, once they've moved
+    /// `travelled` pixels towards the end of the fill. Each one keeps to its own row, scattered so
+    /// they don't line up.
+    fn bubbles(rows: &mut [u8], (width, stride): (usize, usize), (fg, _, pct): (Oklaba, Oklaba, f32), spacing: u32, travelled: f64) {
+        let height = row_count(rows, (width, stride));
+        let fill_width = (width as f32 * pct) as usize;
+        if fill_width == 0 || height == 0 {
+            return;
+        }
+        let fg_color = to_argb(fg);
+        let bubble_color = to_argb(fg.lighten(0.5));
+        let spacing = spacing as f64;
+        // Bubble n is `travelled - n * spacing` along, counting from the start of the bar
+        let first = ((travelled - fill_width as f64) / spacing).floor() as i64 + 1;
+        let last = (travelled / spacing).floor() as i64;
+        for n in first..=last {
+            let x = (travelled - n as f64 * spacing) as usize;
+            let y = (n.rem_euclid(BUBBLE_CYCLE) as usize).wrapping_mul(2_654_435_761) >> 8;
+            let offset = (y % height) * stride + x.min(fill_width - 1) * 4;
+            let pixel = &mut rows[offset..offset + 4];
+            // Only the fill itself bubbles, not the gaps between dots
+            if pixel == fg_color.as_slice() {
+                pixel.copy_from_slice(&bubble_color);
+            }
+        }
+    }
+
+    /// Brighten the filled part of `rows` around a highlight `phase` of the way along it
+    fn shimmer(rows: &mut [u8], shape: (usize, usize), (fg, _, pct): (Oklaba, Oklaba, f32), phase: f32) {
+        let fill_width = (shape.0 as f32 * pct) as usize;
+        let fg_color = to_argb(fg);
+        let center = fill_width as f32 * phase;
+        let spread = (fill_width as f32 / 8.).max(4.);
+        let highlight: Vec<[u8; 4]> = (0..fill_width)
+            .map(|x| {
+                let distance = (x as f32 - center) / spread;
+                to_argb(fg.lighten(0.4 * (-distance * distance).exp()))
+            })
+            .collect();
+        for row in pixel_rows(rows, shape) {
+            for (chunk, color) in row.chunks_exact_mut(4).zip(&highlight) {
+                // Only the fill itself shines, not the gaps between dots
+                if chunk == fg_color.as_slice() {
+                    chunk.copy_from_slice(color.as_slice());
+                }
+            }
+        }
+    }
 
-        let to_u32 = |color| {
-            LinSrgba::from_color(color).into_encoding::<palette::encoding::Srgb>().into_format::<u8,u8>().into_u32::<palette::rgb::channels::Argb>().to_le_bytes()
-        } ;
+    /// Paint the last [`CHARGING_EDGE`] pixels of a fill `pct` of the way along `rows` in `color`,
+    /// counting from the right if `reverse`
+    fn charging_edge(rows: &mut [u8], shape: (usize, usize), pct: f32, color: [u8; 4], reverse: bool) {
+        let width = shape.0;
+        let fill_width = (width as f32 * pct) as usize;
+        let start = fill_width.saturating_sub(CHARGING_EDGE);
+        let (start, end) = if reverse { (width - fill_width, width - start) } else { (start, fill_width) };
+        for row in pixel_rows(rows, shape) {
+            fill_pixels(&mut row[start * 4..end * 4], color);
+        }
+    }
 
+    /// Draw a one pixel line across `rows` `pct` of the way along them, counting from the right
+    /// if `reverse`
+    fn peak_marker(rows: &mut [u8], shape: (usize, usize), pct: f32, color: [u8; 4], reverse: bool) {
+        let width = shape.0;
+        let x = ((width as f32 * pct) as usize).min(width.saturating_sub(1));
+        let x = if reverse { width - 1 - x } else { x };
+        for row in pixel_rows(rows, shape) {
+            row[x * 4..x * 4 + 4].copy_from_slice(&color);
+        }
+    }
 
-        let fg_color = to_u32(base_color);
-        let bg_color = to_u32(bg_color);
+    /// Trace `history` across `rows`, oldest on the left and now on the right, with full at the top
+    fn sparkline(cli: &Cli, rows: &mut [u8], (width, stride): (usize, usize), history: &VecDeque<(Instant, f32)>, window: Duration, color: [u8; 4]) {
+        let height = row_count(rows, (width, stride));
+        if width == 0 || height == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut samples = history.iter().peekable();
+        let mut level = None;
+        for x in 0..width {
+            let ago = window.mul_f32(1. - (x + 1) as f32 / width as f32);
+            let time = now.checked_sub(ago).unwrap_or(now);
+            // The level at any moment is the last one reported before it
+            while let Some(&&(sampled, sample)) = samples.peek() {
+                if sampled > time {
+                    break;
+                }
+                level = Some(sample);
+                samples.next();
+            }
+            if let Some(level) = level {
+                let y = ((1. - cli.curve.apply(level)) * (height - 1) as f32).round() as usize;
+                rows[y * stride + x * 4..y * stride + x * 4 + 4].copy_from_slice(&color);
+            }
+        }
+    }
+
+    /// Flip `rows` end to end
+    fn mirror(rows: &mut [u8], shape: (usize, usize)) {
+        for row in pixel_rows(rows, shape) {
+            // Reversing the bytes reverses the order of the pixels, but also the channels within them
+            row.reverse();
+            row.chunks_exact_mut(4).for_each(|pixel| pixel.reverse());
+        }
+    }
+
+    /// Fill `rows` to show `state`, drawn in `colors`
+    fn fill_status(cli: &Cli, style: Style, started: Instant, rows: &mut [u8], shape: (usize, usize), state: Option<PowerState>, colors: (Oklaba, Oklaba, f32)) {
+        if matches!(state, Some(PowerState { state: ChargeState::Absent, .. })) {
+            Self::fill_absent(rows, shape, colors);
+        } else {
+            Self::fill(style, rows, shape, colors);
+        }
+        if let Some(spacing) = cli.charging_bubbles.filter(|_| matches!(state, Some(PowerState { state: ChargeState::Charging, .. }))) {
+            // Wrapped to a whole cycle of bubbles, which keeps it precise however long wattbar runs
+            let cycle = spacing as f64 * BUBBLE_CYCLE as f64;
+            let travelled = (started.elapsed().as_secs_f64() * cli.bubble_speed as f64) % cycle;
+            Self::bubbles(rows, shape, colors, spacing, travelled);
+        }
+        if cli.charging_shimmer && matches!(state, Some(PowerState { state: ChargeState::Charging, .. })) {
+            let phase = started.elapsed().as_secs_f32() / SHIMMER_PERIOD.as_secs_f32();
+            Self::shimmer(rows, shape, colors, phase.fract());
+        }
+        if matches!(state, Some(state) if cli.reversed(state.state)) {
+            Self::mirror(rows, shape);
+        }
+    }
+
+    /// Draw a battery icon filling `rows`: an outlined body with a nub on its right, and the bar
+    /// inside
+    fn fill_icon(cli: &Cli, style: Style, started: Instant, rows: &mut [u8], shape: (usize, usize), state: Option<PowerState>, colors: (Oklaba, Oklaba, f32)) {
+        let (width, height) = (shape.0, row_count(rows, shape));
+        let outline = to_argb(Oklaba::new(0.8, 0., 0., 1.));
+
+        let nub_width = (width / 12).max(1);
+        let body_width = width.saturating_sub(nub_width);
+        let nub_rows = height / 3..height - height / 3;
+        for (y, row) in pixel_rows(rows, shape).enumerate() {
+            fill_pixels(row, [0; 4]);
+            if y == 0 || y == height - 1 {
+                fill_pixels(&mut row[..body_width * 4], outline);
+            } else if body_width > 0 {
+                row[..4].copy_from_slice(&outline);
+                row[(body_width - 1) * 4..body_width * 4].copy_from_slice(&outline);
+            }
+            if nub_rows.contains(&y) {
+                fill_pixels(&mut row[body_width * 4..], outline);
+            }
+        }
+
+        // The bar goes inside the outline, with a pixel's gap all round
+        const INSET: usize = 2;
+        if body_width <= 2 * INSET || height <= 2 * INSET {
+            return;
+        }
+        let inner_width = body_width - 2 * INSET;
+        let inner_shape = (inner_width, inner_width * 4);
+        let mut inner = vec![0; inner_shape.1 * (height - 2 * INSET)];
+        Self::fill_status(cli, style, started, &mut inner, inner_shape, state, colors);
+        for (row, inner_row) in pixel_rows(rows, shape).skip(INSET).zip(inner.chunks_exact(inner_shape.1)) {
+            row[INSET * 4..INSET * 4 + inner_shape.1].copy_from_slice(inner_row);
+        }
+    }
+
+    /// Fill `rows` with the colors for every level in `state`, the fill along the top and the
+    /// track along the bottom
+    fn fill_test_colors(cli: &Cli, accent: Oklaba, state: ChargeState, rows: &mut [u8], shape: (usize, usize)) {
+        let (width, height) = (shape.0, row_count(rows, shape));
+        // A single row only has room for the fill
+        let fill_rows = if height > 1 { height / 2 } else { height };
+        for x in 0..width {
+            let level = x as f32 / (width - 1).max(1) as f32;
+            let status = PowerState { level, state, time_remaining: None, energy_rate: None };
+            let (fg, bg, _) = Self::colors(cli, accent, Some(status));
+            let (fg, bg) = (to_argb(fg), to_argb(bg));
+            for (y, row) in pixel_rows(rows, shape).enumerate() {
+                row[x * 4..x * 4 + 4].copy_from_slice(if y < fill_rows { &fg } else { &bg });
+            }
+        }
+    }
+
+    /// Lay a shadow over the first [`SHADOW_ROWS`] of `rows`, darkest on the first, leaving at
+    /// least half of a thin bar unshaded
+    fn shadow(rows: &mut [u8], shape: (usize, usize)) {
+        let band = SHADOW_ROWS.min(row_count(rows, shape) / 2);
+        for (y, row) in pixel_rows(rows, shape).take(band).enumerate() {
+            let opacity = SHADOW_OPACITY * (band - y) as f32 / band as f32;
+            for pixel in row.chunks_exact_mut(4) {
+                // Black over a premultiplied pixel: the color darkens and the alpha fills in
+                pixel[..3].iter_mut().for_each(|channel| *channel = (*channel as f32 * (1. - opacity)).round() as u8);
+                pixel[3] = (pixel[3] as f32 + (255. - pixel[3] as f32) * opacity).round() as u8;
+            }
+        }
+    }
+
+    /// Ramp `rows` from transparent to opaque over `length` pixels at each end
+    fn fade_ends(rows: &mut [u8], shape: (usize, usize), length: u32) {
+        let width = shape.0;
+        for row in pixel_rows(rows, shape) {
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let distance = x.min(width - 1 - x) as f32 + 0.5;
+                if distance >= length as f32 {
+                    continue;
+                }
+                // The buffer is premultiplied, so the color fades along with the alpha
+                let opacity = distance / length as f32;
+                pixel.iter_mut().for_each(|channel| *channel = (*channel as f32 * opacity).round() as u8);
+            }
+        }
+    }
+
+    /// Fill `rows` with a bar `pct` full
+    fn fill(style: Style, rows: &mut [u8], (width, stride): (usize, usize), (fg, bg, pct): (Oklaba, Oklaba, f32)) {
+        let fg_color = to_argb(fg);
+        let gap_color = to_argb(bg.darken(0.5));
+        let bg_color = to_argb(bg);
         // eprintln!("Colors: {:?}/{:?}", fg_color, bg_color);
 
         // let pct = pct * 0.75 + 0.125;
-        // blit the buffer
         let fill_width = (width as f32 * pct) as usize * 4;
-        for row in canvas.chunks_exact_mut(stride as usize) {
-            // println!("Filling ..{}", fill_width);
-            row[..fill_width].chunks_exact_mut(4).for_each(|chunk| chunk.copy_from_slice(fg_color.as_slice()));
-            row[fill_width..].chunks_exact_mut(4).for_each(|chunk| chunk.copy_from_slice(bg_color.as_slice()));
+        if rows.len() < width * 4 {
+            return;
         }
+        // Every row of the bar is the same, so only the first is drawn and the rest copied from it
+        let (first, rest) = rows.split_at_mut(stride.min(rows.len()));
+        let first = &mut first[..width * 4];
+        match style {
+            Style::Solid => {
+                // println!("Filling ..{}", fill_width);
+                fill_pixels(&mut first[..fill_width], fg_color);
+                fill_pixels(&mut first[fill_width..], bg_color);
+            }
+            Style::Drained => {
+                // The used-up part carries the color, and what's left is a neutral gray
+                let remaining_color = to_argb(Oklaba::new(bg.l, 0., 0., bg.alpha));
+                fill_pixels(&mut first[..fill_width], remaining_color);
+                fill_pixels(&mut first[fill_width..], fg_color);
+            }
+            Style::Dots => {
+                // Each dot is DOT_PITCH pixels wide, the last of which is left dark to separate it
+                // from its neighbor. A dot is lit if its center is below the current level.
+                let lit_dots = ((width as f32 * pct) / DOT_PITCH as f32).round() as usize;
+                for (dot, pixels) in first.chunks_mut(DOT_PITCH * 4).enumerate() {
+                    let (body, gap) = pixels.split_at_mut((DOT_PITCH - 1).min(pixels.len() / 4) * 4);
+                    fill_pixels(body, if dot < lit_dots { fg_color } else { bg_color });
+                    fill_pixels(gap, gap_color);
+                }
+            }
+        }
+        for row in pixel_rows(rest, (width, stride)) {
+            row.copy_from_slice(first);
+        }
+    }
+
+    /// Redraw the bar; `trigger` is only used for diagnostics
+    fn draw(&mut self, trigger: RenderEvent) {
+        if self.hidden || self.dimensions.0 == 0 || self.dimensions.1 == 0 {
+            return;
+        }
+        let start = Instant::now();
+        let (surface_width, surface_height) = (self.dimensions.0 as i32, self.dimensions.1 as i32);
+        // Everything is drawn as though the bar were horizontal, then turned to fit its edge
+        let (width, height) = if self.edge.is_vertical() {
+            (surface_height, surface_width)
+        } else {
+            (surface_width, surface_height)
+        };
+        let stride = 4 * width;
+        let shape = (width as usize, stride as usize);
+        let border = self.cli.border_all;
+        let segment = if border { self.segment() } else { (0., 1.) };
+
+        // The scratch frame is always packed tightly, but the buffer's rows are only known to be
+        // `surface_stride` bytes apart, so that's what the copy below goes by
+        let surface_stride = 4 * surface_width as usize;
+        let (surface_canvas, buffer) = self
+            .pool
+            .buffer(surface_width, surface_height, surface_stride as i32, wl_shm::Format::Argb8888)
+            .unwrap();
+        self.scratch.resize((stride * height) as usize, 0);
+        let canvas = self.scratch.as_mut_slice();
+        let allocated = Instant::now();
+
+        let cli = &self.cli;
+        let accent = self.accent.read().map_or(None, |accent| *accent).unwrap_or(cli.accent_fallback);
+        let state = Self::read_status(cli, &self.display_status);
+        let mut colors = Self::colors(cli, accent, state);
+        let reverse = matches!(state, Some(state) if cli.reversed(state.state));
+        colors.2 = Self::segment_level(Self::directed(segment, reverse), colors.2);
+        if state.is_none() && cli.startup_animation {
+            // Breathe while waiting for the first update, to show that we're alive
+            let phase = self.started.elapsed().as_secs_f32() / BREATH_PERIOD.as_secs_f32() * std::f32::consts::TAU;
+            let dimming = 0.3 * (1. - phase.cos());
+            colors.0 = colors.0.darken(dimming);
+            colors.1 = colors.1.darken(dimming);
+        }
+        let idle_dimming = self.idleness.get().map_or(0., Idleness::dimming);
+        // Stale data keeps a hint of its color, enough to tell charging from discharging
+        let chroma = |health: &WatcherHealth| if health.stale.load(Ordering::Relaxed) { 0.25 } else { 1. };
+        let fade = |color: Oklaba, chroma: f32| {
+            let color = color.darken(idle_dimming);
+            Oklaba::new(color.l, color.a * chroma, color.b * chroma, color.alpha)
+        };
+        let dim = |(fg, bg, pct): (Oklaba, Oklaba, f32), chroma: f32| (fade(fg, chroma), fade(bg, chroma), pct);
+        colors = dim(colors, chroma(&self.health));
+
+        // A secondary battery gets a third of the bar along its bottom edge
+        let secondary = match &self.secondary_status {
+            Some(status) if height > 1 => {
+                let state = Self::read_status(cli, status);
+                let reverse = matches!(state, Some(state) if cli.reversed(state.state));
+                let absent = matches!(state, Some(PowerState { state: ChargeState::Absent, .. }));
+                let chroma = self.secondary_health.as_deref().map_or(1., chroma);
+                let (fg, bg, pct) = dim(Self::colors(cli, accent, state), chroma);
+                Some(((fg, bg, Self::segment_level(Self::directed(segment, reverse), pct)), reverse, absent))
+            }
+            _ => None,
+        };
+        let secondary_rows = if secondary.is_some() { (height as usize / 3).max(1) } else { 0 };
+        let (main_rows, secondary_rows) = canvas.split_at_mut((height as usize - secondary_rows) * stride as usize);
+
+        // The rate gauge needs a row of its own, so it's only drawn if there's more than one
+        let rate_width = match (cli.show_rate, state.and_then(|state| state.energy_rate)) {
+            (Some(max_rate), Some(rate)) if main_rows.len() > stride as usize => {
+                Some((width as f32 * (rate.abs() / max_rate).min(1.)) as usize * 4)
+            }
+            _ => None,
+        };
+
+        // blit the buffer
+        if let Some(state) = cli.test_colors {
+            Self::fill_test_colors(cli, accent, state, canvas, shape);
+        } else if self.flashing {
+            let white = to_argb(Oklaba::new(1., 0., 0., 1.));
+            pixel_rows(canvas, shape).for_each(|row| fill_pixels(row, white));
+        } else if cli.icon.is_some() {
+            Self::fill_icon(cli, self.style, self.started, canvas, shape, state, colors);
+        } else {
+            Self::fill_status(cli, self.style, self.started, main_rows, shape, state, colors);
+            if let (Some(color), Some(PowerState { state: ChargeState::Charging, .. })) = (cli.charging_edge, state) {
+                // Like the peak marker, the edge only shows on the segment the level falls in
+                let pct = colors.2;
+                if pct > 0. && (pct < 1. || !border) {
+                    let color = to_argb(color.resolve(accent).darken(idle_dimming));
+                    Self::charging_edge(main_rows, shape, pct, color, reverse);
+                }
+            }
+            if let (true, Some((peak_state, peak))) = (cli.show_peak, self.peak.get()) {
+                let pct = Self::segment_level(Self::directed(segment, reverse), peak);
+                // In a frame, only the segment the peak falls in gets the marker
+                if peak_state != ChargeState::Absent && pct > 0. && (pct < 1. || !border) {
+                    Self::peak_marker(main_rows, shape, pct, to_argb(colors.0.lighten(0.5)), reverse);
+                }
+            }
+            if let Some((colors, reverse, absent)) = secondary {
+                if absent {
+                    Self::fill_absent(secondary_rows, shape, colors);
+                } else {
+                    Self::fill(self.style, secondary_rows, shape, colors);
+                }
+                if reverse {
+                    Self::mirror(secondary_rows, shape);
+                }
+            }
+
+            if let Some(window) = cli.sparkline.filter(|_| main_rows.len() >= 4 * stride as usize) {
+                let color = to_argb(colors.0.lighten(0.5));
+                Self::sparkline(cli, main_rows, shape, &self.history.borrow(), window, color);
+            }
+
+            if let Some(rate_width) = rate_width {
+                let rate_color = to_argb(colors.0.lighten(0.5));
+                fill_pixels(&mut canvas[..rate_width], rate_color);
+            }
+        }
+
+        if flipped(cli, self.edge, self.reversed) {
+            // Flipping the whole bar keeps the rate gauge and secondary battery lined up with it
+            Self::mirror(canvas, shape);
+        }
+
+        if let Some(outline) = cli.outline {
+            let outline = to_argb(outline.resolve(accent));
+            let last_row = height as usize - 1;
+            for (y, row) in pixel_rows(canvas, shape).enumerate() {
+                if y == 0 || y == last_row {
+                    fill_pixels(row, outline);
+                }
+                let last_pixel = row.len() - 4;
+                row[..4].copy_from_slice(outline.as_slice());
+                row[last_pixel..].copy_from_slice(outline.as_slice());
+            }
+        }
+
+        // The first row is the one furthest from the screen edge; an icon has no inside to shade
+        if cli.shadow && cli.icon.is_none() {
+            Self::shadow(canvas, shape);
+        }
+
+        if let Some(length) = cli.fade_ends {
+            Self::fade_ends(canvas, shape, length);
+        }
+
+        let (length, thickness) = (width as usize, height as usize);
+        for (y, row) in pixel_rows(canvas, shape).enumerate() {
+            if self.edge == Edge::Top || (self.edge == Edge::Bottom && !border) {
+                // Horizontal bars only move whole rows around, so they can be copied as such
+                let (_, surface_y) = self.edge.to_surface((0, y), (length, thickness), border);
+                let offset = surface_y * surface_stride;
+                surface_canvas[offset..offset + row.len()].copy_from_slice(row);
+                continue;
+            }
+            for (x, pixel) in row.chunks_exact(4).enumerate() {
+                let (surface_x, surface_y) = self.edge.to_surface((x, y), (length, thickness), border);
+                let offset = surface_y * surface_stride + surface_x * 4;
+                surface_canvas[offset..offset + 4].copy_from_slice(pixel);
+            }
+        }
+
+        let filled = Instant::now();
 
         self.surface.attach(Some(&buffer), 0, 0);
-        self.surface.damage_buffer(0, 0, width, height);
+        self.surface.damage_buffer(0, 0, surface_width, surface_height);
         self.surface.commit();
+        self.showing_status = state.is_some();
+
+        if let Some(threshold) = self.cli.debug_frame_time {
+            let total = start.elapsed();
+            if total >= threshold {
+                let output = with_output_info(&self.output, output_name).unwrap_or_else(|| "(unknown)".to_owned());
+                eprintln!(
+                    "Slow frame on output {} after {:?}: {:?} total ({:?} allocating, {:?} drawing, {:?} committing)",
+                    output,
+                    trigger,
+                    total,
+                    allocated - start,
+                    filled - allocated,
+                    filled.elapsed(),
+                );
+            }
+        }
+    }
+}
+
+/// Have the compositor tell us when the user has been idle for `timeout`, for `--dim-on-idle`
+fn watch_idle(env: &Environment<MyEnv>, timeout: Duration, state: &AppState) -> Option<Main<ExtIdleNotificationV1>> {
+    let notifier = match env.get_global::<ExtIdleNotifierV1>() {
+        Some(notifier) => notifier,
+        None => {
+            eprintln!("The compositor doesn't support idle notifications; ignoring --dim-on-idle");
+            return None;
+        }
+    };
+    let seat = match env.get_all_seats().into_iter().next() {
+        Some(seat) => seat,
+        None => {
+            eprintln!("There's no seat to watch for activity; ignoring --dim-on-idle");
+            return None;
+        }
+    };
+
+    // Too long a timeout is refused while parsing the command line
+    let timeout = u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX);
+    let notification = notifier.get_idle_notification(timeout, &seat);
+    let idleness = Rc::clone(&state.idleness);
+    let timers = Rc::clone(&state.timers);
+    notification.quick_assign(move |_, event, _| {
+        let idle = match event {
+            ext_idle_notification_v1::Event::Idled => true,
+            ext_idle_notification_v1::Event::Resumed => false,
+        };
+        idleness.set(Some(Idleness { idle, since: Instant::now() }));
+        timers.schedule(TimerPurpose::IdleFade, Duration::ZERO);
+    });
+    Some(notification)
+}
+
+impl AppState {
+    /// How each battery's watcher is doing, in the same order as [`AppState::appearance`] lists
+    /// their statuses
+    fn healths(&self) -> Vec<Arc<WatcherHealth>> {
+        std::iter::once(&self.display_health)
+            .chain(self.edge_health.iter().map(|(_, health)| health))
+            .chain(&self.secondary_health)
+            .cloned()
+            .collect()
+    }
+
+    fn appearance(&self) -> Appearance {
+        let cli = &self.cli;
+        let statuses = std::iter::once(&self.display_status)
+            .chain(self.edge_status.iter().map(|(_, status)| status))
+            .chain(&self.secondary_status);
+        let statuses = statuses
+            .map(|status| {
+                status.read().map_or(None, |lock| *lock).map(|state| {
+                    let state = state.reconciled(cli.full_threshold).quantized(cli.quantize);
+                    let critical = matches!((cli.critical_time, state.time_remaining), (Some(threshold), Some(time)) if time < threshold);
+                    let rate = state.energy_rate.filter(|_| cli.show_rate.is_some());
+                    (state.state, state.level, rate, critical)
+                })
+            })
+            .collect();
+        (statuses, self.accent.read().map_or(None, |accent| *accent))
     }
 }
 
+/// What we know of a toplevel, for `--fullscreen-behavior`
+#[derive(Default)]
+struct Toplevel {
+    /// Global ids of the outputs it's on
+    outputs: Vec<u32>,
+    fullscreen: bool,
+    /// State sent since the last `done`
+    pending_fullscreen: bool,
+}
+
+/// Keep `state.fullscreen_outputs` up to date with the outputs showing a fullscreen window, for
+/// `--fullscreen-behavior`
+fn watch_fullscreen(env: &Environment<MyEnv>, state: &AppState) -> Option<Main<ZwlrForeignToplevelManagerV1>> {
+    // Fullscreen state only arrived in version 2
+    let manager = match env.manager.instantiate_range::<ZwlrForeignToplevelManagerV1>(2, 3) {
+        Ok(manager) => manager,
+        Err(_) => {
+            eprintln!("The compositor doesn't share which windows are fullscreen; ignoring --fullscreen-behavior");
+            return None;
+        }
+    };
+
+    let toplevels: Rc<RefCell<HashMap<u32, Toplevel>>> = Rc::default();
+    let fullscreen_outputs = Rc::clone(&state.fullscreen_outputs);
+    manager.quick_assign(move |_, event, _| {
+        let handle = match event {
+            zwlr_foreign_toplevel_manager_v1::Event::Toplevel { toplevel } => toplevel,
+            _ => return,
+        };
+        let id = handle.as_ref().id();
+        toplevels.borrow_mut().insert(id, Toplevel::default());
+        let toplevels = Rc::clone(&toplevels);
+        let fullscreen_outputs = Rc::clone(&fullscreen_outputs);
+        handle.quick_assign(move |handle, event, _| {
+            let mut toplevels = toplevels.borrow_mut();
+            match event {
+                zwlr_foreign_toplevel_handle_v1::Event::OutputEnter { output } => {
+                    if let (Some(toplevel), Some(output)) = (toplevels.get_mut(&id), with_output_info(&output, |info| info.id)) {
+                        toplevel.outputs.push(output);
+                    }
+                }
+                zwlr_foreign_toplevel_handle_v1::Event::OutputLeave { output } => {
+                    if let (Some(toplevel), Some(output)) = (toplevels.get_mut(&id), with_output_info(&output, |info| info.id)) {
+                        toplevel.outputs.retain(|&id| id != output);
+                    }
+                }
+                zwlr_foreign_toplevel_handle_v1::Event::State { state } => {
+                    // An array of native-endian u32s
+                    let fullscreen = zwlr_foreign_toplevel_handle_v1::State::Fullscreen as u32;
+                    let minimized = zwlr_foreign_toplevel_handle_v1::State::Minimized as u32;
+                    let states: Vec<u32> = state.chunks_exact(4).map(|state| u32::from_ne_bytes([state[0], state[1], state[2], state[3]])).collect();
+                    if let Some(toplevel) = toplevels.get_mut(&id) {
+                        toplevel.pending_fullscreen = states.contains(&fullscreen) && !states.contains(&minimized);
+                    }
+                }
+                zwlr_foreign_toplevel_handle_v1::Event::Done => {
+                    if let Some(toplevel) = toplevels.get_mut(&id) {
+                        toplevel.fullscreen = toplevel.pending_fullscreen;
+                    }
+                }
+                zwlr_foreign_toplevel_handle_v1::Event::Closed => {
+                    toplevels.remove(&id);
+                    handle.destroy();
+                }
+                _ => return,
+            }
+            *fullscreen_outputs.borrow_mut() = toplevels
+                .values()
+                .filter(|toplevel| toplevel.fullscreen)
+                .flat_map(|toplevel| toplevel.outputs.iter().copied())
+                .collect();
+        });
+    });
+    Some(manager)
+}
+
+/// The state the bar shows the battery in, once it's been reconciled with `--full-threshold`
+fn charge_state(cli: &Cli, status: &RwLock<Option<PowerState>>) -> Option<ChargeState> {
+    status.read().map_or(None, |lock| lock.map(|status| status.reconciled(cli.full_threshold).state))
+}
+
+/// The keyboard interactivity to give a layer surface of protocol version `version`. The bar only
+/// displays information, so unless asked, it never takes focus away from whatever the user is
+/// typing into.
+fn keyboard_interactivity(cli: &Cli, version: u32) -> zwlr_layer_surface_v1::KeyboardInteractivity {
+    if !cli.keyboard_interactive {
+        zwlr_layer_surface_v1::KeyboardInteractivity::None
+    } else if version >= 4 {
+        zwlr_layer_surface_v1::KeyboardInteractivity::OnDemand
+    } else {
+        // Before version 4 this was a boolean, and true meant exclusive
+        zwlr_layer_surface_v1::KeyboardInteractivity::Exclusive
+    }
+}
+
+/// Raise `peak` to the level of `state`, starting over if the battery has changed state
+fn update_peak(peak: &Cell<Peak>, state: Option<PowerState>) {
+    peak.set(match (state, peak.get()) {
+        (Some(state), Some((peak_state, peak))) if state.state == peak_state => Some((peak_state, peak.max(state.level))),
+        (Some(state), _) => Some((state.state, state.level)),
+        (None, peak) => peak,
+    });
+}
+
+/// Whether anything on the bar moves while the battery is in `state`
+fn animating(cli: &Cli, state: Option<ChargeState>) -> bool {
+    match state {
+        None => cli.startup_animation,
+        Some(state) => (cli.charging_shimmer || cli.charging_bubbles.is_some()) && state == ChargeState::Charging,
+    }
+}
+
+/// Time between frames of an animation. There's no point going faster than the fastest output
+/// can show them.
+fn animation_frame(cli: &Cli, surfaces: &[(u32, Surface)]) -> Duration {
+    let frame = cli.max_fps.map_or(ANIMATION_FRAME, |fps| Duration::from_secs_f32(1. / fps as f32));
+    let refresh = surfaces.iter().filter_map(|(_, surface)| surface.mode).map(|mode| mode.refresh_rate).max();
+    match refresh {
+        // Refresh rates are in millihertz
+        Some(refresh) if refresh > 0 => frame.max(Duration::from_secs_f32(1000. / refresh as f32)),
+        _ => frame,
+    }
+}
+
+/// The exclusive zone for a bar along `edge` drawn at `(width, height)` logical pixels
+fn exclusive_zone(cli: &Cli, edge: Edge, (width, height): (u32, u32)) -> i32 {
+    let thickness = if cli.icon.is_some() {
+        // An icon only covers a corner, so it has no edge to keep windows away from unless
+        // it's been given one, and then it reserves its own thickness across that edge
+        match cli.exclusive_edge {
+            Some(edge) if edge.is_vertical() => Some(width),
+            Some(_) => Some(height),
+            None => None,
+        }
+    } else if edge.is_vertical() {
+        Some(width)
+    } else {
+        Some(height)
+    };
+    thickness.map_or(0, |thickness| cli.exclusive_zone(thickness))
+}
+
+/// Whether the bar along `edge` is drawn end to end from the way it fills clockwise, given
+/// whether its output is `reversed`
+fn flipped(cli: &Cli, edge: Edge, reversed: bool) -> bool {
+    // Clockwise, the right bar fills downwards; the left one already fills upwards
+    let upwards = cli.vertical_from_bottom && edge == Edge::Right;
+    reversed != upwards
+}
+
+/// The scale factor to go by for an output reporting `scale_factor`. Nothing sensible has a scale
+/// below 1, and it's divided by later.
+fn output_scale(scale_factor: i32) -> i32 {
+    scale_factor.max(1)
+}
+
+/// A name for the output, preferring the one the compositor advertises
+fn output_name(info: &OutputInfo) -> String {
+    if info.name.is_empty() {
+        format!("#{}", info.id)
+    } else {
+        info.name.clone()
+    }
+}
+
+/// Anchor to both edges that meet at `corner`
+fn corner_anchor(corner: Corner) -> zwlr_layer_surface_v1::Anchor {
+    use zwlr_layer_surface_v1::Anchor;
+    match corner {
+        Corner::TopLeft => Anchor::Top | Anchor::Left,
+        Corner::TopRight => Anchor::Top | Anchor::Right,
+        Corner::BottomLeft => Anchor::Bottom | Anchor::Left,
+        Corner::BottomRight => Anchor::Bottom | Anchor::Right,
+    }
+}
+
+/// The color of a charging battery, which also stands in while the status is unknown
+fn charging_color(theme: Theme) -> Oklaba {
+    match theme {
+        Theme::Default => Oklaba::from_color_unclamped(Srgba::new(0., 0.5, 1., 1.0f32)),
+        Theme::Cividis => Oklaba::from_color_unclamped(Srgba::new(0.4, 0.7, 1., 1.0f32)),
+    }
+}
+
+/// The number of rows in `rows`, which are `stride` bytes apart and each `width` pixels long. The
+/// last row needn't have its padding. A buffer with no size has no rows.
+fn row_count(rows: &[u8], (width, stride): (usize, usize)) -> usize {
+    if rows.is_empty() || stride == 0 {
+        return 0;
+    }
+    (rows.len() + stride - width * 4) / stride
+}
+
+/// The pixels of each row of `rows`, which are `stride` bytes apart and each `width` pixels long,
+/// leaving out any padding at the end of the row
+fn pixel_rows(rows: &mut [u8], (width, stride): (usize, usize)) -> impl Iterator<Item = &mut [u8]> {
+    let height = row_count(rows, (width, stride));
+    rows.chunks_mut(stride.max(1)).take(height).map(move |row| &mut row[..width * 4])
+}
+
+/// Fill `pixels` with `color`, doubling the filled part each step rather than writing a pixel at a time
+fn fill_pixels(pixels: &mut [u8], color: [u8; 4]) {
+    if pixels.len() < 4 {
+        return;
+    }
+    pixels[..4].copy_from_slice(&color);
+    let mut filled = 4;
+    while filled < pixels.len() {
+        let run = filled.min(pixels.len() - filled);
+        pixels.copy_within(..run, filled);
+        filled += run;
+    }
+}
+
+fn to_argb(color: Oklaba) -> [u8; 4] {
+    LinSrgba::from_color(color).into_encoding::<palette::encoding::Srgb>().into_format::<u8,u8>().into_u32::<palette::rgb::channels::Argb>().to_le_bytes()
+}
+
 impl Drop for Surface {
     fn drop(&mut self) {
-        self.layer_surface.destroy();
+        self.timers.cancel(TimerPurpose::Resize(self.output_id, self.edge));
+        // The role has to go before the surface it's attached to
+        match &mut self.role {
+            Role::Layer(layer_surface) => layer_surface.destroy(),
+            Role::Window(window) => drop(window.take()),
+        }
         self.surface.destroy();
     }
 }
 
+/// Check the configuration and the battery backend, without connecting to Wayland
+fn dry_run(cli: &Cli) -> anyhow::Result<()> {
+    println!(
+        "Bar: {}px thick, {}px from the edge, reserving {}px, {:?} style",
+        cli.size,
+        cli.margin,
+        cli.exclusive_zone(cli.size),
+        cli.style(cli.size),
+    );
+    if cli.mock_upower {
+        println!("Battery: simulated");
+        return Ok(());
+    }
+    let state = match cli.backend {
+        Backend::Upower => upower::probe(None, &cli.properties).context("Unable to query UPower")?,
+        Backend::Acpi => acpi::probe()?,
+    };
+    print_battery("Battery", state);
+    // The other batteries always come from UPower, whichever backend the main one uses
+    if let Some(path) = &cli.secondary_battery {
+        let state = upower::probe(Some(path.clone()), &cli.properties)
+            .with_context(|| format!("Unable to query the secondary battery {}", path))?;
+        print_battery("Secondary battery", state);
+    }
+    for (edge, path) in &cli.edge_battery {
+        let state = upower::probe(Some(path.clone()), &cli.properties)
+            .with_context(|| format!("Unable to query the {:?} edge's battery {}", edge, path))?;
+        print_battery(&format!("{:?} edge battery", edge), state);
+    }
+    Ok(())
+}
+
+/// Describe `state` for `--dry-run`
+fn print_battery(label: &str, state: PowerState) {
+    let time_remaining = match state.time_remaining {
+        Some(time) => format!("{}:{:02}", time as u32 / 3600, time as u32 / 60 % 60),
+        None => "—".to_owned(),
+    };
+    println!(
+        "{}: {:.0}%, {:?}, {} remaining",
+        label,
+        state.level * 100.,
+        state.state,
+        time_remaining,
+    );
+}
+
 fn main() -> anyhow::Result<()> {
+    let cli = Arc::new(Cli::parse()?);
+    if cli.print_config {
+        print!("{}", cli.resolved_config());
+        return Ok(());
+    }
+    if cli.dry_run {
+        return dry_run(&cli);
+    }
+    if cli.require_battery && !cli.mock_upower {
+        let has_battery = match cli.backend {
+            Backend::Upower => upower::has_battery().context("Unable to list UPower's devices")?,
+            Backend::Acpi => !matches!(acpi::probe(), Err(_) | Ok(PowerState { state: ChargeState::Absent, .. })),
+        };
+        if !has_battery {
+            eprintln!("There's no battery to show; exiting");
+            return Ok(());
+        }
+    }
 
-    let app_state = AppState::default();
+    let (timers, timer_source) = Timers::new()?;
+    let app_state = AppState {
+        display_status: Arc::default(),
+        edge_status: cli.edge_battery.iter().map(|(edge, _)| (*edge, Arc::default())).collect(),
+        secondary_status: cli.secondary_battery.as_ref().map(|_| Arc::default()),
+        accent: Arc::default(),
+        idleness: Rc::default(),
+        fullscreen_outputs: Rc::default(),
+        display_health: Arc::default(),
+        edge_health: cli.edge_battery.iter().map(|(edge, _)| (*edge, Arc::default())).collect(),
+        secondary_health: cli.secondary_battery.as_ref().map(|_| Arc::default()),
+        display_peak: Rc::default(),
+        edge_peak: cli.edge_battery.iter().map(|(edge, _)| (*edge, Rc::default())).collect(),
+        history: Rc::default(),
+        cli: Arc::clone(&cli),
+        timers: Rc::new(timers),
+        started: Instant::now(),
+    };
 
     // Spawn upower watcher
     let upower_channel = {
@@ -234,40 +1699,99 @@ fn main() -> anyhow::Result<()> {
         let reporter = upower::PowerReporter {
             sender,
             status: Arc::clone(&app_state.display_status),
+            health: Arc::clone(&app_state.display_health),
         };
 
-        upower::spawn_upower(reporter)?;
-        // upower::spawn_mock(reporter)?;
+        if cli.uses_accent() {
+            // Not worth failing over; the fallback color will do
+            if let Err(err) = portal::spawn_accent_watcher(reporter.sender.clone(), Arc::clone(&app_state.accent)) {
+                eprintln!("Accent color unavailable, using the fallback: {}", err);
+            }
+        }
+
+        for (((_, path), (_, status)), (_, health)) in cli.edge_battery.iter().zip(&app_state.edge_status).zip(&app_state.edge_health) {
+            let reporter = upower::PowerReporter {
+                sender: reporter.sender.clone(),
+                status: Arc::clone(status),
+                health: Arc::clone(health),
+            };
+            upower::spawn_upower(reporter, Some(path.clone()), cli.properties.clone())?;
+        }
+
+        if let (Some(path), Some(status), Some(health)) = (&cli.secondary_battery, &app_state.secondary_status, &app_state.secondary_health) {
+            let secondary = upower::PowerReporter {
+                sender: reporter.sender.clone(),
+                status: Arc::clone(status),
+                health: Arc::clone(health),
+            };
+            upower::spawn_upower(secondary, Some(path.clone()), cli.properties.clone())?;
+        }
+
+        if cli.mock_upower {
+            upower::spawn_mock(reporter, cli.mock_speed, cli.mock_sweep, cli.mock_state)?;
+        } else {
+            match cli.backend {
+                Backend::Upower => upower::spawn_upower(reporter, None, cli.properties.clone())?,
+                Backend::Acpi => acpi::spawn_acpi(reporter)?,
+            }
+        }
         channel
     };
     
-    let (env, display, queue) =
-        new_default_environment!(MyEnv, fields = [layer_shell: SimpleGlobal::new(),],)?;
+    let (env, display, queue) = connect()?;
 
     let env_handle = env.clone();
 
-    let layer_shell = env.require_global::<ZwlrLayerShellV1>();
+    let shell = match env.get_global::<ZwlrLayerShellV1>() {
+        Some(layer_shell) => Shell::Layer(layer_shell),
+        None => {
+            eprintln!("Layer shell is unavailable; falling back to a plain window, which can't stay at the edge of the screen or reserve space");
+            Shell::Window(env.clone())
+        }
+    };
 
     // List surfaces
     let surfaces = Rc::new(RefCell::new(Vec::new()));
 
     let surfaces_handle = Rc::clone(&surfaces);
     let app_state_handle = app_state.clone();
-    let output_handler = move |output: WlOutput, info: &OutputInfo| {
+    // Every output still connected, so a fallback window can move to another when its own goes
+    let mut outputs: Vec<(u32, WlOutput)> = Vec::new();
+    let mut output_handler = move |output: WlOutput, info: &OutputInfo| {
+        let create = |output: &WlOutput, id: u32, edges: &[Edge]| {
+            for &edge in edges {
+                let surface = env_handle.create_surface().detach();
+                let pool = env_handle
+                    .create_auto_pool()
+                    .expect("Failed to create a memeory pool!");
+                surfaces_handle.borrow_mut().push((
+                    id,
+                    Surface::new(output, surface, &shell, pool, &app_state_handle, edge),
+                ));
+            }
+        };
         if info.obsolete {
+            outputs.retain(|(i, _)| *i != info.id);
             surfaces_handle.borrow_mut().retain(|(i, _)| *i != info.id);
+            if surfaces_handle.borrow().is_empty() {
+                match (&shell, outputs.first()) {
+                    (Shell::Window(_), Some((id, output))) => create(output, *id, &[Edge::Bottom]),
+                    _ => eprintln!("The last output has gone; waiting for another to show the bar on"),
+                }
+            }
             output.release();
         } else {
-            let surface = env_handle.create_surface().detach();
-            let pool = env_handle
-                .create_auto_pool()
-                .expect("Failed to create a memeory pool!");
-            surfaces_handle.borrow_mut().push((
-                info.id,
-                Surface::new(&output, surface, &layer_shell.clone(), pool, &app_state_handle),
-            ));
-
-            // output.
+            outputs.push((info.id, output.clone()));
+            // The top and bottom bars go first, so that they span the corners and the sides fit
+            // between them
+            let edges: &[Edge] = match shell {
+                Shell::Layer(_) if app_state_handle.cli.border_all => &[Edge::Top, Edge::Bottom, Edge::Right, Edge::Left],
+                Shell::Layer(_) => &[Edge::Bottom],
+                // Windows can't be put on a particular output anyway, so one will do
+                Shell::Window(_) if !surfaces_handle.borrow().is_empty() => &[],
+                Shell::Window(_) => &[Edge::Bottom],
+            };
+            create(&output, info.id, edges);
         }
     };
 
@@ -277,19 +1801,154 @@ fn main() -> anyhow::Result<()> {
             output_handler(output, &info);
         }
     }
+    if surfaces.borrow().is_empty() {
+        // Nothing to draw on yet, but the listener below will catch any output that turns up
+        eprintln!("No outputs yet; the bar will appear when one is connected");
+    }
 
     let _listener_handle =
         env.listen_for_outputs(move |output, info, _| output_handler(output, info));
+    let _toplevel_manager = match cli.fullscreen_behavior {
+        FullscreenBehavior::Ignore => None,
+        _ => watch_fullscreen(&env, &app_state),
+    };
+    // Notifications stop when they're destroyed, so this one has to live as long as we do
+    let _idle_notification = cli.dim_on_idle.and_then(|timeout| watch_idle(&env, timeout, &app_state));
+
     let mut event_loop = calloop::EventLoop::<()>::try_new().expect("Failed to start event loop");
 
     let surfaces_handle = Rc::clone(&surfaces);
+    let flash_status = Arc::clone(&app_state.display_status);
+    let last_state = Cell::new(None);
+    let last_appearance = RefCell::new(None);
+    let history_handle = Rc::clone(&app_state.history);
+    let healths = app_state.healths();
+    let last_reports = vec![Cell::new(0); healths.len()];
+    let app_state_handle = app_state.clone();
+    let cli_handle = Arc::clone(&cli);
+    let timers_handle = Rc::clone(&app_state.timers);
     event_loop.handle().insert_source(
         upower_channel,
         move |_, _, _| {
+            // Every battery and the accent color share the channel, so a wakeup only keeps a
+            // battery fresh if its own watcher reported
+            for (index, (health, last_reports)) in healths.iter().zip(&last_reports).enumerate() {
+                let reports = health.reports.load(Ordering::Relaxed);
+                if health.down.load(Ordering::Relaxed) {
+                    // The last status stays up, washed out as though it had gone stale
+                    if !health.stale.swap(true, Ordering::Relaxed) {
+                        last_appearance.replace(None);
+                    }
+                } else if last_reports.replace(reports) != reports {
+                    if let Some(stale_after) = cli_handle.stale_after {
+                        timers_handle.schedule(TimerPurpose::Stale(index), stale_after);
+                    }
+                    if health.stale.swap(false, Ordering::Relaxed) {
+                        // Coming back to life is worth a redraw even if nothing else changed
+                        last_appearance.replace(None);
+                    }
+                }
+            }
+            if cli_handle.show_peak {
+                // Kept here rather than by each bar, so that every output shows the same peak,
+                // however late it was plugged in
+                update_peak(&app_state_handle.display_peak, Surface::read_status(&cli_handle, &flash_status));
+                for ((_, peak), (_, status)) in app_state_handle.edge_peak.iter().zip(&app_state_handle.edge_status) {
+                    update_peak(peak, Surface::read_status(&cli_handle, status));
+                }
+            }
+            if let Some(window) = cli_handle.sparkline {
+                let mut history = history_handle.borrow_mut();
+                let now = Instant::now();
+                if let Some(level) = flash_status.read().map_or(None, |lock| lock.map(|status| status.level)) {
+                    history.push_back((now, level));
+                }
+                // Keep the last sample from before the window, which says where the line starts
+                while history.len() > 1 && now.duration_since(history[1].0) > window {
+                    history.pop_front();
+                }
+            }
+            if cli_handle.quantize.is_some() {
+                let appearance = Some(app_state_handle.appearance());
+                if *last_appearance.borrow() == appearance {
+                    return;
+                }
+                last_appearance.replace(appearance);
+            }
             // eprintln!("Power state: {:?}", &*power_state_handle.read().unwrap());
-            for (_, surface) in surfaces_handle.borrow_mut().iter() {
-                if surface.next_render_event.get().is_none() {
-                    surface.next_render_event.set(Some(RenderEvent::DataChanged));
+            let state = charge_state(&cli_handle, &flash_status);
+            let previous = last_state.replace(state);
+            // Start animating on entering a state that calls for it, e.g. waiting for data again
+            // because the watcher is restarting
+            if animating(&cli_handle, state) && !animating(&cli_handle, previous) {
+                let frame = animation_frame(&cli_handle, &surfaces_handle.borrow());
+                timers_handle.schedule(TimerPurpose::Animation, frame);
+            }
+            let finished_charging = previous == Some(ChargeState::Charging)
+                && state == Some(ChargeState::FullyCharged);
+            let flash = match cli_handle.full_flash {
+                Some(duration) if finished_charging => {
+                    timers_handle.schedule(TimerPurpose::FlashEnd, duration);
+                    true
+                }
+                _ => false,
+            };
+            for (_, surface) in surfaces_handle.borrow_mut().iter_mut() {
+                surface.flashing |= flash;
+                surface.data_changed();
+            }
+        }
+    ).unwrap();
+
+    if cli.startup_animation {
+        app_state.timers.schedule(TimerPurpose::Animation, animation_frame(&cli, &surfaces.borrow()));
+    }
+    let surfaces_handle = Rc::clone(&surfaces);
+    let timers_handle = Rc::clone(&app_state.timers);
+    let animation_status = Arc::clone(&app_state.display_status);
+    let idle_handle = Rc::clone(&app_state.idleness);
+    let healths = app_state.healths();
+    let cli_handle = Arc::clone(&cli);
+    event_loop.handle().insert_source(
+        timer_source,
+        move |purpose, _, _| {
+            timers_handle.expired(purpose);
+            let mut surfaces = surfaces_handle.borrow_mut();
+            match purpose {
+                TimerPurpose::Resize(id, edge) => {
+                    for (_, surface) in surfaces.iter().filter(|(i, surface)| *i == id && surface.edge == edge) {
+                        surface.apply_size();
+                        surface.surface.commit();
+                    }
+                }
+                TimerPurpose::Animation => {
+                    let state = charge_state(&cli_handle, &animation_status);
+                    if animating(&cli_handle, state) {
+                        timers_handle.schedule(TimerPurpose::Animation, animation_frame(&cli_handle, &surfaces));
+                        for (_, surface) in surfaces.iter() {
+                            surface.data_changed();
+                        }
+                    }
+                }
+                TimerPurpose::IdleFade => {
+                    if matches!(idle_handle.get(), Some(idleness) if idleness.fading()) {
+                        timers_handle.schedule(TimerPurpose::IdleFade, animation_frame(&cli_handle, &surfaces));
+                    }
+                    for (_, surface) in surfaces.iter() {
+                        surface.data_changed();
+                    }
+                }
+                TimerPurpose::Stale(index) => {
+                    healths[index].stale.store(true, Ordering::Relaxed);
+                    for (_, surface) in surfaces.iter() {
+                        surface.data_changed();
+                    }
+                }
+                TimerPurpose::FlashEnd => {
+                    for (_, surface) in surfaces.iter_mut() {
+                        surface.flashing = false;
+                        surface.data_changed();
+                    }
                 }
             }
         }
@@ -299,6 +1958,7 @@ fn main() -> anyhow::Result<()> {
     WaylandSource::new(queue)
         .quick_insert(event_loop.handle())
         .unwrap();
+    let mut finished_at = None;
     loop {
         {
             let mut surfaces = surfaces.borrow_mut();
@@ -312,10 +1972,294 @@ fn main() -> anyhow::Result<()> {
             }
         }
 
+        if cli.once && finished_at.is_none() {
+            let surfaces = surfaces.borrow();
+            if !surfaces.is_empty() && surfaces.iter().all(|(_, surface)| surface.showing_status) {
+                finished_at = Some(Instant::now());
+            }
+        }
+
         display.flush().unwrap();
-        event_loop.dispatch(None, &mut ()).unwrap();
+        match finished_at {
+            Some(finished_at) => {
+                let linger = ONCE_LINGER.saturating_sub(finished_at.elapsed());
+                if linger.is_zero() {
+                    break;
+                }
+                event_loop.dispatch(linger, &mut ()).unwrap();
+            }
+            None => event_loop.dispatch(None, &mut ()).unwrap(),
+        }
     }
 
-    
-    //println!("Registry: {:#?}", env);
+    // Take the bars down properly, so that the compositor isn't left holding our buffers
+    surfaces.borrow_mut().clear();
+    display.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for whatever was in the buffer before, and for the padding at the end of rows
+    const PADDING: u8 = 0xab;
+
+    fn colors(pct: f32) -> (Oklaba, Oklaba, f32) {
+        (Oklaba::new(0.8, 0., 0., 1.), Oklaba::new(0.3, 0., 0., 1.), pct)
+    }
+
+    #[test]
+    fn fill_leaves_row_padding_alone() {
+        let (width, stride, height) = (5, 4 * 5 + 12, 3);
+        let mut rows = vec![PADDING; stride * height];
+        Surface::fill(Style::Solid, &mut rows, (width, stride), colors(0.4));
+        Surface::mirror(&mut rows, (width, stride));
+        let (fg, bg) = (to_argb(colors(0.).0), to_argb(colors(0.).1));
+        for row in rows.chunks_exact(stride) {
+            let (pixels, padding) = row.split_at(width * 4);
+            let expected: Vec<u8> = [bg, bg, bg, fg, fg].concat();
+            assert_eq!(pixels, expected.as_slice());
+            assert!(padding.iter().all(|&byte| byte == PADDING));
+        }
+    }
+
+    /// Draw a side bar of the frame `pct` full, as `draw` would, and return which of its rows on
+    /// screen are lit, from the top down
+    fn side_bar(cli: &Cli, edge: Edge, pct: f32) -> Vec<bool> {
+        let (length, thickness) = (8, 1);
+        let shape = (length, 4 * length);
+        let mut rows = vec![0; 4 * length * thickness];
+        let state = PowerState { level: pct, state: ChargeState::Discharging, time_remaining: None, energy_rate: None };
+        Surface::fill_status(cli, Style::Solid, Instant::now(), &mut rows, shape, Some(state), colors(pct));
+        if flipped(cli, edge, false) {
+            Surface::mirror(&mut rows, shape);
+        }
+        let fg = to_argb(colors(pct).0);
+        let mut lit = vec![false; length];
+        for (x, pixel) in rows.chunks_exact(4).enumerate() {
+            let (_, y) = edge.to_surface((x, 0), (length, thickness), true);
+            lit[y] = pixel == fg;
+        }
+        lit
+    }
+
+    const TOP_QUARTER: [bool; 8] = [true, true, false, false, false, false, false, false];
+    const TOP_THREE_QUARTERS: [bool; 8] = [true, true, true, true, true, true, false, false];
+    const BOTTOM_QUARTER: [bool; 8] = [false, false, false, false, false, false, true, true];
+    const BOTTOM_THREE_QUARTERS: [bool; 8] = [false, false, true, true, true, true, true, true];
+
+    #[test]
+    fn side_bars_fill_clockwise() {
+        let cli = Cli { border_all: true, ..Cli::default() };
+        assert_eq!(side_bar(&cli, Edge::Right, 0.25), TOP_QUARTER);
+        assert_eq!(side_bar(&cli, Edge::Right, 0.75), TOP_THREE_QUARTERS);
+        assert_eq!(side_bar(&cli, Edge::Left, 0.25), BOTTOM_QUARTER);
+        assert_eq!(side_bar(&cli, Edge::Left, 0.75), BOTTOM_THREE_QUARTERS);
+    }
+
+    #[test]
+    fn side_bars_fill_from_the_bottom() {
+        let cli = Cli { border_all: true, vertical_from_bottom: true, ..Cli::default() };
+        for edge in [Edge::Right, Edge::Left] {
+            assert_eq!(side_bar(&cli, edge, 0.25), BOTTOM_QUARTER);
+            assert_eq!(side_bar(&cli, edge, 0.75), BOTTOM_THREE_QUARTERS);
+        }
+    }
+
+    #[test]
+    fn exclusive_zone_matches_the_drawn_thickness() {
+        let cli = Cli { size: 6, margin: 2, ..Cli::default() };
+        for scale in [1, 2, 3] {
+            let requested = Surface::size_request(&cli, Edge::Bottom, cli.size, Some((3840, 2160)), scale);
+            assert_eq!(requested, (3840 / scale as u32, 6));
+            // Before the first configure, and when the compositor goes with the request
+            for configured in [(0, 0), requested] {
+                let drawn = Surface::drawn(requested, configured);
+                assert_eq!(drawn, requested);
+                assert_eq!(exclusive_zone(&cli, Edge::Bottom, drawn), drawn.1 as i32 + 2);
+            }
+        }
+        // Scaling a 2880x1800 mode by 1.5, the compositor reports a scale of 2 and so gets asked
+        // for a 1440px wide bar, then configures the logical 1920px, rounding the thickness to
+        // whole device pixels
+        let requested = Surface::size_request(&cli, Edge::Bottom, cli.size, Some((2880, 1800)), 2);
+        assert_eq!(requested, (1440, 6));
+        let drawn = Surface::drawn(requested, (1920, 7));
+        assert_eq!(drawn, (1920, 7));
+        assert_eq!(exclusive_zone(&cli, Edge::Bottom, drawn), 9);
+        // Side bars are only ever given a length by the compositor
+        let requested = Surface::size_request(&cli, Edge::Left, cli.size, Some((2880, 1800)), 2);
+        assert_eq!(requested, (6, 0));
+        let drawn = Surface::drawn(requested, (0, 1200));
+        assert_eq!(exclusive_zone(&cli, Edge::Left, drawn), 8);
+        assert_eq!(exclusive_zone(&cli, Edge::Left, Surface::drawn(requested, (7, 1200))), 9);
+        let cli = Cli { reserve_margin: false, ..cli };
+        assert_eq!(exclusive_zone(&cli, Edge::Left, drawn), 6);
+        assert_eq!(exclusive_zone(&Cli { reserve: Some(10), ..cli }, Edge::Left, drawn), 10);
+    }
+
+    fn reconciled(state: ChargeState, level: f32, full_threshold: Option<f32>) -> ChargeState {
+        PowerState { level, state, time_remaining: None, energy_rate: None }.reconciled(full_threshold).state
+    }
+
+    #[test]
+    fn charging_at_the_threshold_is_full() {
+        assert_eq!(reconciled(ChargeState::Charging, 0.99, Some(0.99)), ChargeState::FullyCharged);
+        assert_eq!(reconciled(ChargeState::Charging, 1., Some(0.99)), ChargeState::FullyCharged);
+    }
+
+    #[test]
+    fn charging_below_the_threshold_is_left_alone() {
+        assert_eq!(reconciled(ChargeState::Charging, 0.98, Some(0.99)), ChargeState::Charging);
+    }
+
+    #[test]
+    fn only_charging_is_reconciled() {
+        for state in [ChargeState::FullyCharged, ChargeState::Discharging, ChargeState::Empty, ChargeState::Absent] {
+            assert_eq!(reconciled(state, 1., Some(0.99)), state);
+            assert_eq!(reconciled(state, 0.5, Some(0.99)), state);
+        }
+    }
+
+    #[test]
+    fn charging_past_the_threshold_does_not_animate() {
+        let cli = Cli { charging_shimmer: true, full_threshold: Some(0.99), ..Cli::default() };
+        let status = |level| RwLock::new(Some(PowerState { level, state: ChargeState::Charging, time_remaining: None, energy_rate: None }));
+        assert!(!animating(&cli, charge_state(&cli, &status(1.))));
+        assert!(animating(&cli, charge_state(&cli, &status(0.5))));
+    }
+
+    #[test]
+    fn no_threshold_reconciles_nothing() {
+        assert_eq!(reconciled(ChargeState::Charging, 1., None), ChargeState::Charging);
+    }
+
+    fn parse(args: &[&str]) -> anyhow::Result<Cli> {
+        Cli::parse_from(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn mock_speed_must_fit_a_duration() {
+        for speed in ["inf", "1e30", "0", "-1", "NaN"] {
+            assert!(parse(&["--mock-speed", speed]).is_err(), "{}", speed);
+        }
+        assert_eq!(parse(&["--mock-speed", "2"]).unwrap().mock_speed, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn edge_batteries_need_a_upower_frame() {
+        assert!(parse(&["--edge-battery", "left=/a"]).is_err());
+        assert!(parse(&["--border-all", "--edge-battery", "left=/a", "--backend", "acpi"]).is_err());
+        assert!(parse(&["--border-all", "--edge-battery", "left=/a", "--mock-upower"]).is_err());
+        assert!(parse(&["--border-all", "--edge-battery", "left=/a"]).is_ok());
+    }
+
+    #[test]
+    fn resolved_config_is_toml() {
+        let cli = parse(&[
+            "--output-size", "Écran\u{7f}\"1\"=8",
+            "--output-reverse", "DP-1\\2",
+            "--namespace", "bar\tcafé",
+            "--length", "33.333333",
+        ]).unwrap();
+        let config: toml::Value = toml::from_str(&cli.resolved_config()).unwrap();
+        assert_eq!(config["namespace"].as_str(), Some("bar\tcafé"));
+        assert_eq!(config["length"].as_str(), Some("33.33%"));
+        assert_eq!(config["output"]["Écran\u{7f}\"1\""]["size"].as_integer(), Some(8));
+        assert_eq!(config["output"]["DP-1\\2"]["reversed"].as_bool(), Some(true));
+    }
+
+    #[test]
+    fn min_contrast_applies_to_the_track() {
+        let state = PowerState { level: 0.5, state: ChargeState::Discharging, time_remaining: None, energy_rate: None };
+        let accent = Oklaba::new(0.6, 0., 0., 1.);
+        let backdrop = Some(color::UserColor::Fixed(Oklaba::new(0.5, 0., 0., 1.)));
+        for cli in [Cli { min_contrast: Some(5.), ..Cli::default() }, Cli { min_contrast: Some(5.), backdrop, ..Cli::default() }] {
+            for state in [Some(state), Some(PowerState { state: ChargeState::Charging, ..state }), None] {
+                let (fg, bg, _) = Surface::colors(&cli, accent, state);
+                assert!(color::contrast_ratio(fg, bg) >= 5., "{:?} on {:?}", fg, bg);
+            }
+        }
+        // Without it, the default track is short of that, and --backdrop can leave the two the same
+        let backdrop = Cli { backdrop, ..Cli::default() };
+        for state in [Some(state), Some(PowerState { state: ChargeState::Charging, ..state }), None] {
+            let (fg, bg, _) = Surface::colors(&Cli::default(), accent, state);
+            assert!(color::contrast_ratio(fg, bg) < 5.);
+            let (fg, bg, _) = Surface::colors(&backdrop, accent, state);
+            assert!(color::contrast_ratio(fg, bg) < 1.1);
+        }
+    }
+
+    #[test]
+    fn never_takes_focus_unless_asked() {
+        use zwlr_layer_surface_v1::KeyboardInteractivity;
+        for version in 1..=5 {
+            assert_eq!(keyboard_interactivity(&Cli::default(), version), KeyboardInteractivity::None);
+        }
+        let cli = Cli { keyboard_interactive: true, ..Cli::default() };
+        assert_eq!(keyboard_interactivity(&cli, 3), KeyboardInteractivity::Exclusive);
+        assert_eq!(keyboard_interactivity(&cli, 4), KeyboardInteractivity::OnDemand);
+    }
+
+    #[test]
+    fn peak_starts_over_with_each_state() {
+        let status = |level, state| Some(PowerState { level, state, time_remaining: None, energy_rate: None });
+        let peak = Cell::new(None);
+        update_peak(&peak, status(0.4, ChargeState::Charging));
+        update_peak(&peak, status(0.6, ChargeState::Charging));
+        update_peak(&peak, status(0.5, ChargeState::Charging));
+        assert_eq!(peak.get(), Some((ChargeState::Charging, 0.6)));
+        // Losing the status altogether keeps what it was
+        update_peak(&peak, None);
+        assert_eq!(peak.get(), Some((ChargeState::Charging, 0.6)));
+        update_peak(&peak, status(0.5, ChargeState::Discharging));
+        assert_eq!(peak.get(), Some((ChargeState::Discharging, 0.5)));
+    }
+
+    #[test]
+    fn output_scale_is_at_least_one() {
+        for scale_factor in [i32::MIN, -2, 0] {
+            assert_eq!(output_scale(scale_factor), 1);
+            assert_eq!(1920 / output_scale(scale_factor), 1920);
+        }
+        assert_eq!(output_scale(1), 1);
+        assert_eq!(output_scale(2), 2);
+    }
+
+    #[test]
+    fn row_count_allows_for_a_short_last_row() {
+        assert_eq!(row_count(&[0; 3 * 32], (5, 32)), 3);
+        assert_eq!(row_count(&[0; 2 * 32 + 20], (5, 32)), 3);
+    }
+
+    #[test]
+    fn sizes_on_a_bogus_output() {
+        // A scale of 0 and a mode of no size, as a misbehaving compositor might report
+        let scale = output_scale(0);
+        let cli = Cli { size: 6, ..Cli::default() };
+        assert_eq!(Surface::size_request(&cli, Edge::Bottom, 6, Some((0, 0)), scale), (0, 6));
+        assert_eq!(Surface::size_request(&cli, Edge::Left, 6, Some((0, 0)), scale), (6, 0));
+        let cli = Cli { length: Some(0.5), ..cli };
+        assert_eq!(Surface::size_request(&cli, Edge::Bottom, 6, Some((0, 0)), scale), (1, 6));
+        assert_eq!(Surface::size_request(&cli, Edge::Right, 6, Some((0, 0)), scale), (6, 1));
+        // Without a mode at all
+        assert_eq!(Surface::size_request(&cli, Edge::Bottom, 6, None, scale), (FALLBACK_WINDOW_WIDTH / 2, 6));
+        let cli = Cli { length: None, ..cli };
+        assert_eq!(Surface::size_request(&cli, Edge::Bottom, 6, None, scale), (0, 6));
+        // Nor a configure to go by, which leaves the width at 0 and so nothing to draw
+        assert_eq!(Surface::drawn((0, 6), (0, 0)), (0, 6));
+    }
+
+    #[test]
+    fn zero_sized_buffers_have_no_rows() {
+        for shape in [(0, 0), (0, 32), (5, 20)] {
+            assert_eq!(row_count(&[], shape), 0);
+            assert_eq!(pixel_rows(&mut [], shape).count(), 0);
+        }
+        assert_eq!(row_count(&[0; 64], (0, 0)), 0);
+        assert_eq!(pixel_rows(&mut [0; 64], (0, 0)).count(), 0);
+        let mut rows = [0; 64];
+        assert!(pixel_rows(&mut rows, (0, 32)).all(|row| row.is_empty()));
+    }
 }
@@ -0,0 +1,61 @@
+use std::sync::{Arc, RwLock};
+
+use calloop::channel::Sender as CalloopSender;
+use palette::convert::FromColorUnclamped;
+use palette::{Oklaba, Srgb};
+use zbus::zvariant::{OwnedValue, Value};
+
+const PORTAL_DESTINATION: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const SETTINGS_INTERFACE: &str = "org.freedesktop.portal.Settings";
+const APPEARANCE_NAMESPACE: &str = "org.freedesktop.appearance";
+const ACCENT_KEY: &str = "accent-color";
+
+/// Read the desktop's accent color into `accent`, and keep it up to date in the background,
+/// notifying `sender` whenever it changes.
+///
+/// Fails if the settings portal isn't running or doesn't know about accent colors, in which case
+/// `accent` is left alone.
+pub fn spawn_accent_watcher(sender: CalloopSender<()>, accent: Arc<RwLock<Option<Oklaba>>>) -> anyhow::Result<()> {
+    let dbus = zbus::blocking::Connection::session()?;
+    let proxy = zbus::blocking::Proxy::new(&dbus, PORTAL_DESTINATION, PORTAL_PATH, SETTINGS_INTERFACE)?;
+    // Subscribe before reading, so that a change in between isn't lost
+    let changes = proxy.receive_signal("SettingChanged")?;
+    let initial: OwnedValue = proxy.call("Read", &(APPEARANCE_NAMESPACE, ACCENT_KEY))?;
+    *accent.write().unwrap() = accent_color(&initial);
+
+    std::thread::spawn(move || {
+        for message in changes {
+            let (namespace, key, value): (String, String, OwnedValue) = match message.body() {
+                Ok(body) => body,
+                Err(err) => {
+                    eprintln!("Malformed portal setting change: {}", err);
+                    continue;
+                }
+            };
+            if namespace == APPEARANCE_NAMESPACE && key == ACCENT_KEY {
+                *accent.write().unwrap() = accent_color(&value);
+                if sender.send(()).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Decode an accent color setting, which is an sRGB triple. Components outside [0, 1] mean that
+/// the user hasn't chosen an accent color.
+fn accent_color(value: &Value) -> Option<Oklaba> {
+    match value {
+        // Read wraps the setting in an extra variant
+        Value::Value(inner) => accent_color(inner),
+        Value::Structure(rgb) => match rgb.fields() {
+            [Value::F64(r), Value::F64(g), Value::F64(b)] if [r, g, b].iter().all(|c| (0. ..=1.).contains(*c)) => {
+                Some(Oklaba::from_color_unclamped(Srgb::new(*r as f32, *g as f32, *b as f32)))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,63 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use calloop::timer::{Timeout, Timer, TimerHandle};
+
+use crate::Edge;
+
+/// What a timer is for. There is at most one pending timer for each purpose.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TimerPurpose {
+    /// Apply a settled mode or scale change to the bar along this edge of the output with this
+    /// global id. Each bar of a frame has its own, so dropping one leaves the others' alone.
+    Resize(u32, Edge),
+    /// Stop the full-charge flash
+    FlashEnd,
+    /// Draw the next frame of an animation
+    Animation,
+    /// Draw the next frame of the `--dim-on-idle` fade
+    IdleFade,
+    /// No update has come for `--stale-after` from the watcher at this index of
+    /// `AppState::healths`
+    Stale(usize),
+}
+
+/// Timeouts on the event loop, keyed by purpose so that features which need timers don't step on
+/// each other or leave stale timeouts behind.
+pub struct Timers {
+    handle: TimerHandle<TimerPurpose>,
+    pending: RefCell<HashMap<TimerPurpose, Timeout>>,
+}
+
+impl Timers {
+    /// Create the timer registry, along with the event source that must be inserted into the event
+    /// loop for the timers to fire. The source's callback must call [`Timers::expired`].
+    pub fn new() -> std::io::Result<(Self, Timer<TimerPurpose>)> {
+        let timer = Timer::new()?;
+        let timers = Timers {
+            handle: timer.handle(),
+            pending: RefCell::default(),
+        };
+        Ok((timers, timer))
+    }
+
+    /// Fire `purpose` after `delay`, replacing any timer already pending for it
+    pub fn schedule(&self, purpose: TimerPurpose, delay: Duration) {
+        let timeout = self.handle.add_timeout(delay, purpose);
+        if let Some(previous) = self.pending.borrow_mut().insert(purpose, timeout) {
+            self.handle.cancel_timeout(&previous);
+        }
+    }
+
+    pub fn cancel(&self, purpose: TimerPurpose) {
+        if let Some(timeout) = self.pending.borrow_mut().remove(&purpose) {
+            self.handle.cancel_timeout(&timeout);
+        }
+    }
+
+    /// Record that the timer for `purpose` has fired
+    pub fn expired(&self, purpose: TimerPurpose) {
+        self.pending.borrow_mut().remove(&purpose);
+    }
+}
@@ -1,11 +1,16 @@
 use std::collections::HashMap;
-use crate::PowerState;
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+use crate::{ChargeState, PowerState};
+use crate::cli::{MockState, MockSweep, PropertyNames};
 use std::sync::mpsc::SyncSender;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{
     Arc, RwLock,
 };
 use upower_dbus;
 
+use anyhow::anyhow;
 use calloop::channel::Sender as CalloopSender;
 use upower_dbus::BatteryState;
 use zbus;
@@ -14,89 +19,242 @@ use zbus::zvariant::OwnedValue;
 pub struct PowerReporter {
     pub sender: CalloopSender<()>,
     pub status: Arc<RwLock<Option<PowerState>>>,
+    /// How the bar keeps track of this watcher. The last status stays in place while the watcher
+    /// is down, so that the bar can wash it out rather than lose it.
+    pub health: Arc<WatcherHealth>,
 }
 
-pub fn spawn_mock(reporter: PowerReporter) -> anyhow::Result<()> {
+/// What the bar knows of a watcher beyond its latest status
+#[derive(Debug, Default)]
+pub struct WatcherHealth {
+    /// Raised while the watcher is down
+    pub down: AtomicBool,
+    /// Number of statuses reported, so the bar can tell a fresh one from other wakeups
+    pub reports: AtomicUsize,
+    /// Raised by the bar while the status is out of date, either because the watcher is down or
+    /// because it's gone `--stale-after` without a report
+    pub stale: AtomicBool,
+}
+
+impl PowerReporter {
+    /// Publish `state` and let the bar know. Returns false once the bar has gone away.
+    pub fn report(&self, state: Option<PowerState>) -> bool {
+        if let Ok(mut status) = self.status.write() {
+            *status = state;
+        }
+        self.health.down.store(false, Ordering::Relaxed);
+        self.health.reports.fetch_add(1, Ordering::Relaxed);
+        self.sender.send(()).is_ok()
+    }
+
+    /// Note that the status can't be read for now, so that the bar stops showing it as current
+    pub fn fail(&self) {
+        self.health.down.store(true, Ordering::Relaxed);
+        self.sender.send(()).ok();
+    }
+}
+
+/// How long to wait before restarting a failed UPower watcher
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// Number of steps the mock battery takes to sweep from empty to full
+const MOCK_STEPS: u32 = 0x200;
+
+pub fn spawn_mock(reporter: PowerReporter, sweep_time: Duration, sweep: MockSweep, hold: Option<MockState>) -> anyhow::Result<()> {
     std::thread::spawn(move || {
-        *reporter.status.write().unwrap() = Some(PowerState{
-            level: 0.0,
-            charging: false,
-            time_remaining: 0.0,
-        });
+        let sweep_state = if sweep == MockSweep::Discharging { ChargeState::Discharging } else { ChargeState::Charging };
+        let initial = hold.unwrap_or(MockState { level: 0.0, state: sweep_state });
+        let mut status = PowerState {
+            level: initial.level,
+            state: initial.state,
+            time_remaining: None,
+            energy_rate: None,
+        };
+        reporter.report(Some(status));
+        if hold.is_some() {
+            // Nothing will ever change, so there's no need to keep the thread around
+            return;
+        }
+
+        let step = sweep_time / MOCK_STEPS;
         let mut fill = 0u32;
        loop {
-           std::thread::sleep(std::time::Duration::from_millis(10));
-           {
-               let mut lock = reporter.status.write().unwrap();
-               fill = (fill + 1) & 0x1FF;
-               lock.as_mut().unwrap().level = (fill as f32) / 512.0f32;
-           };
-           reporter.sender.send(()).unwrap();
+           std::thread::sleep(step);
+           if sweep == MockSweep::Tour {
+               // Up while charging, a pause full, down while discharging, then a pause each
+               // empty and without a battery
+               const PAUSE: u32 = MOCK_STEPS / 4;
+               fill = (fill + 1) % (2 * MOCK_STEPS + 3 * PAUSE);
+               let (level, state) = match fill {
+                   f if f < MOCK_STEPS => (f, ChargeState::Charging),
+                   f if f < MOCK_STEPS + PAUSE => (MOCK_STEPS, ChargeState::FullyCharged),
+                   f if f < 2 * MOCK_STEPS + PAUSE => (2 * MOCK_STEPS + PAUSE - f, ChargeState::Discharging),
+                   f if f < 2 * MOCK_STEPS + 2 * PAUSE => (0, ChargeState::Empty),
+                   _ => (0, ChargeState::Absent),
+               };
+               status.level = level as f32 / MOCK_STEPS as f32;
+               status.state = state;
+           } else if sweep == MockSweep::Bounce {
+               // Up to full and back down again, taking twice as many steps
+               fill = (fill + 1) % (2 * MOCK_STEPS);
+               let rising = fill < MOCK_STEPS;
+               let level = if rising { fill } else { 2 * MOCK_STEPS - fill };
+               status.level = level as f32 / MOCK_STEPS as f32;
+               status.state = if rising { ChargeState::Charging } else { ChargeState::Discharging };
+           } else {
+               fill = (fill + 1) % MOCK_STEPS;
+               status.level = (fill as f32) / MOCK_STEPS as f32;
+           }
+           if !reporter.report(Some(status)) {
+               // The bar has gone away
+               return;
+           }
        }
     });
     Ok(())
 }
 
-pub fn spawn_upower(reporter: PowerReporter) -> anyhow::Result<()> {
+/// Watch a UPower device, given by its D-Bus object path, or UPower's display device if `None`.
+///
+/// If the watcher fails after it has started, the reporter is told (so the bar shows that its
+/// data is out of date) and the watcher is restarted after [`RESTART_DELAY`].
+pub fn spawn_upower(reporter: PowerReporter, device: Option<String>, names: PropertyNames) -> anyhow::Result<()> {
     let (start_send, start_receive) = std::sync::mpsc::sync_channel(1);
     std::thread::spawn(move || {
-        let failure = upower_run(reporter, &start_send);
-        if failure.is_err() {
-            start_send.send(failure).unwrap();
+        let mut start_send = Some(start_send);
+        loop {
+            let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                upower_run(&reporter, device.clone(), &names, &mut start_send)
+            })).unwrap_or_else(|_| Err(anyhow!("watcher panicked")));
+
+            if let Some(start_send) = start_send.take() {
+                // We never got going, so let spawn_upower report the problem
+                start_send.send(result.and_then(|()| Err(anyhow!("UPower watcher exited")))).ok();
+                return;
+            }
+
+            match result {
+                Ok(()) => eprintln!("UPower stopped sending updates; restarting watcher in {:?}", RESTART_DELAY),
+                Err(err) => eprintln!("UPower watcher failed: {:#}; restarting in {:?}", err, RESTART_DELAY),
+            }
+            reporter.fail();
+
+            std::thread::sleep(RESTART_DELAY);
         }
     });
 
     start_receive.recv()?
 }
 
-fn upower_update(reporter: &PowerReporter, properties: &HashMap<String, OwnedValue>) {
-    {
-        let mut status = reporter.status.write().unwrap();
-        let battery_state = upower_dbus::BatteryState::try_from(properties["State"].clone()).unwrap();
-        let charging = match battery_state {
-            // fully enumerate the options in case a new one is added.
-            BatteryState::Charging |
-            BatteryState::FullyCharged |
-            BatteryState::PendingCharge => true,
-            BatteryState::Empty |
-            BatteryState::Discharging |
-            BatteryState::PendingDischarge |
-            BatteryState::Unknown => false,
-        };
-        *status = Some(PowerState {
-            level: f64::try_from(&properties["Percentage"]).unwrap() as f32 / 100.0,
-            charging,
-            time_remaining: if charging {
-                i64::try_from(&properties["TimeToFull"]).unwrap()
-            } else {
-                i64::try_from(&properties["TimeToEmpty"]).unwrap()
-            } as f32
-        })
+/// Look up the property called `name`, which the user may have chosen
+fn property<'a>(properties: &'a HashMap<String, OwnedValue>, name: &str) -> anyhow::Result<&'a OwnedValue> {
+    properties.get(name).ok_or_else(|| anyhow!("Device has no {:?} property", name))
+}
+
+/// Interpret the properties of a UPower device, with the values taken from the properties
+/// given by `names`
+fn power_state(properties: &HashMap<String, OwnedValue>, names: &PropertyNames) -> anyhow::Result<PowerState> {
+    let battery_state = upower_dbus::BatteryState::try_from(property(properties, &names.state)?.clone())
+        .map_err(|_| anyhow!("Property {:?} is not a battery state", names.state))?;
+    // The display device stays around when there are no batteries, but says it isn't present
+    let present = !matches!(properties.get("IsPresent").map(bool::try_from), Some(Ok(false)));
+    let state = match battery_state {
+        _ if !present => ChargeState::Absent,
+        // fully enumerate the options in case a new one is added.
+        BatteryState::Charging |
+        BatteryState::PendingCharge => ChargeState::Charging,
+        BatteryState::FullyCharged => ChargeState::FullyCharged,
+        BatteryState::Empty => ChargeState::Empty,
+        BatteryState::Discharging |
+        BatteryState::PendingDischarge |
+        BatteryState::Unknown => ChargeState::Discharging,
+    };
+    let charging = state.is_charging();
+    let level = f64::try_from(property(properties, &names.level)?)
+        .map_err(|_| anyhow!("Property {:?} is not a number", names.level))?;
+    let time_name = if charging { &names.time_to_full } else { &names.time_to_empty };
+    let time_remaining = i64::try_from(property(properties, time_name)?)
+        .map_err(|_| anyhow!("Property {:?} is not an integer", time_name))?;
+    Ok(PowerState {
+        // Raw meters can read past their nominal maximum, or below zero
+        level: (level / names.level_max).clamp(0., 1.) as f32,
+        state,
+        // UPower says 0 while it's still estimating, and nothing sensible is ever negative
+        time_remaining: Some(time_remaining)
+            .filter(|&time| time > 0)
+            .map(|time| time as f32),
+        // UPower reports the magnitude only; a missing or zero rate means the device doesn't know
+        energy_rate: properties.get(&names.energy_rate)
+            .and_then(|rate| f64::try_from(rate).ok())
+            .filter(|&rate| rate != 0.)
+            .map(|rate| if charging { rate as f32 } else { -rate as f32 }),
+    })
+}
+
+fn upower_update(reporter: &PowerReporter, properties: &HashMap<String, OwnedValue>, names: &PropertyNames) -> anyhow::Result<()> {
+    reporter.report(Some(power_state(properties, names)?));
+    Ok(())
+}
+
+fn device_proxy(dbus: &zbus::blocking::Connection, device: Option<String>) -> anyhow::Result<zbus::blocking::fdo::PropertiesProxy<'static>> {
+    let device_path = match device {
+        Some(path) => zbus::zvariant::OwnedObjectPath::try_from(path)?,
+        None => upower_dbus::UPowerProxyBlocking::new(dbus)?.get_display_device()?,
+    };
+    Ok(zbus::blocking::fdo::PropertiesProxy::builder(dbus)
+        .destination("org.freedesktop.UPower")?
+        .path(device_path)?
+        .cache_properties(zbus::CacheProperties::No)
+        .build()?)
+}
+
+/// Check that UPower is reachable, returning the current state of `device`, or of its display
+/// device if none is given
+pub fn probe(device: Option<String>, names: &PropertyNames) -> anyhow::Result<PowerState> {
+    let dbus = zbus::blocking::Connection::system()?;
+    let properties = device_proxy(&dbus, device)?.get_all(device_interface_name())?;
+    power_state(&properties, names)
+}
+
+/// Whether UPower knows of a battery that powers the system, as opposed to having only mains
+/// power, or only the batteries of peripherals such as mice
+pub fn has_battery() -> anyhow::Result<bool> {
+    let dbus = zbus::blocking::Connection::system()?;
+    for path in upower_dbus::UPowerProxyBlocking::new(&dbus)?.enumerate_devices()? {
+        let device = upower_dbus::DeviceProxyBlocking::builder(&dbus).path(path)?.build()?;
+        if device.type_()? == upower_dbus::BatteryType::Battery && device.power_supply()? && device.is_present()? {
+            return Ok(true);
+        }
     }
-    // Notify listeners
-    reporter.sender.send(()).ok();
+    Ok(false)
+}
+
+fn device_interface_name() -> zbus::names::InterfaceName<'static> {
+    zbus::names::InterfaceName::from_static_str("org.freedesktop.UPower.Device").unwrap()
 }
 
+/// Report the state of `device` until something goes wrong. `start_send` is taken and notified
+/// once the first update has been sent.
 fn upower_run(
-    reporter: PowerReporter,
-    start_send: &SyncSender<anyhow::Result<()>>,
+    reporter: &PowerReporter,
+    device: Option<String>,
+    names: &PropertyNames,
+    start_send: &mut Option<SyncSender<anyhow::Result<()>>>,
 ) -> anyhow::Result<()> {
     let dbus = zbus::blocking::Connection::system()?;
-    let display_device_path = upower_dbus::UPowerProxyBlocking::new(&dbus)?.get_display_device()?;
-    let display_proxy : zbus::blocking::fdo::PropertiesProxy = zbus::blocking::fdo::PropertiesProxy::builder(&dbus)
-        .destination("org.freedesktop.UPower")?
-        .path(display_device_path)?
-        .cache_properties(zbus::CacheProperties::No)
-        .build()?;
+    let display_proxy = device_proxy(&dbus, device)?;
 
     let prop_changed_iterator = display_proxy.receive_properties_changed()?;
 
-    let device_interface_name = zbus::names::InterfaceName::from_static_str("org.freedesktop.UPower.Device").unwrap();
+    let device_interface_name = device_interface_name();
 
     let mut properties: HashMap<String, OwnedValue> = display_proxy.get_all(device_interface_name.clone())?;
 
-    upower_update(&reporter, &properties);
-    start_send.send(Ok(())).unwrap();
+    // A mistake in the property names shows up here, before we've reported a successful start
+    upower_update(reporter, &properties, names)?;
+    if let Some(start_send) = start_send.take() {
+        start_send.send(Ok(())).unwrap();
+    }
     for signal in prop_changed_iterator {
         let args = signal.args().expect("Invalid signal arguments");
         if args.interface_name != device_interface_name {
@@ -104,14 +262,72 @@ fn upower_run(
         }
 
         for (name, value) in args.changed_properties {
-            properties.get_mut(name).map(|vp| *vp = value.into());
+            if let Some(vp) = properties.get_mut(name) {
+                *vp = value.into();
+            }
         }
         
         // Update reporter
-        upower_update(&reporter, &properties);
+        upower_update(reporter, &properties, names)?;
     }
 
     // TODO: actually watch for events
     Ok(())
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The properties of a battery in `state` at `level` percent, with `time` seconds to go either
+    /// way and drawing `rate` watts
+    fn properties(state: BatteryState, level: f64, time: i64, rate: f64) -> HashMap<String, OwnedValue> {
+        HashMap::from([
+            ("State".to_owned(), OwnedValue::from(state as u32)),
+            ("Percentage".to_owned(), OwnedValue::from(level)),
+            ("TimeToFull".to_owned(), OwnedValue::from(time)),
+            ("TimeToEmpty".to_owned(), OwnedValue::from(time)),
+            ("EnergyRate".to_owned(), OwnedValue::from(rate)),
+        ])
+    }
+
+    fn power_state(properties: &HashMap<String, OwnedValue>) -> PowerState {
+        super::power_state(properties, &PropertyNames::default()).unwrap()
+    }
+
+    #[test]
+    fn unknown_times_are_none() {
+        for time in [0, -1] {
+            assert_eq!(power_state(&properties(BatteryState::Discharging, 50., time, 5.)).time_remaining, None);
+            assert_eq!(power_state(&properties(BatteryState::Charging, 50., time, 5.)).time_remaining, None);
+        }
+        assert_eq!(power_state(&properties(BatteryState::Discharging, 50., 60, 5.)).time_remaining, Some(60.));
+    }
+
+    #[test]
+    fn a_zero_rate_is_none() {
+        assert_eq!(power_state(&properties(BatteryState::Discharging, 50., 60, 0.)).energy_rate, None);
+    }
+
+    #[test]
+    fn the_rate_follows_the_charge_state() {
+        assert_eq!(power_state(&properties(BatteryState::Charging, 50., 60, 5.)).energy_rate, Some(5.));
+        assert_eq!(power_state(&properties(BatteryState::PendingCharge, 50., 60, 5.)).energy_rate, Some(5.));
+        assert_eq!(power_state(&properties(BatteryState::Discharging, 50., 60, 5.)).energy_rate, Some(-5.));
+        assert_eq!(power_state(&properties(BatteryState::Empty, 0., 60, 5.)).energy_rate, Some(-5.));
+    }
+
+    #[test]
+    fn raw_levels_are_clamped() {
+        let names = PropertyNames { level: "Energy".to_owned(), level_max: 50., ..PropertyNames::default() };
+        let level = |energy: f64| {
+            let mut properties = properties(BatteryState::Discharging, 0., 60, 5.);
+            properties.insert("Energy".to_owned(), OwnedValue::from(energy));
+            super::power_state(&properties, &names).unwrap().level
+        };
+        assert_eq!(level(25.), 0.5);
+        assert_eq!(level(60.), 1.);
+        assert_eq!(level(-1.), 0.);
+    }
+}